@@ -0,0 +1,92 @@
+use crate::error::{KvError, Result};
+use crate::record::{Command, DEFAULT_KEYSPACE_ID};
+use std::collections::HashMap;
+
+/**
+ * ! sets and removes applied as one unit by `KvsEngine::write`
+ * * `KvStore` writes the whole batch as a single checksummed log record, so after a crash
+ * * either every operation of it is recovered or none is
+ * * operations apply in the order they were added, a later one on the same key wins
+ */
+#[derive(Debug, Default)]
+pub struct WriteBatch {
+    cmds: Vec<Command>,
+}
+
+impl WriteBatch {
+    pub fn new() -> WriteBatch {
+        WriteBatch::default()
+    }
+
+    pub fn set_bytes(&mut self, key: &[u8], value: &[u8]) {
+        self.cmds.push(Command::Set {
+            keyspace: DEFAULT_KEYSPACE_ID,
+            key: key.to_vec(),
+            value: value.to_vec(),
+            expires: None,
+        });
+    }
+
+    pub fn remove_bytes(&mut self, key: &[u8]) {
+        self.cmds.push(Command::Remove {
+            keyspace: DEFAULT_KEYSPACE_ID,
+            key: key.to_vec(),
+        });
+    }
+
+    pub fn set(&mut self, key: String, value: String) {
+        self.cmds.push(Command::Set {
+            keyspace: DEFAULT_KEYSPACE_ID,
+            key: key.into_bytes(),
+            value: value.into_bytes(),
+            expires: None,
+        });
+    }
+
+    pub fn remove(&mut self, key: String) {
+        self.cmds.push(Command::Remove {
+            keyspace: DEFAULT_KEYSPACE_ID,
+            key: key.into_bytes(),
+        });
+    }
+
+    pub fn len(&self) -> usize {
+        self.cmds.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.cmds.is_empty()
+    }
+
+    /**
+     * ! `KvError::KeyNotFound` if the batch removes a key that neither the store, as told
+     * ! by `contains`, nor an earlier set of the batch holds
+     * * checked before anything is written, so a failing batch changes nothing
+     */
+    pub(crate) fn check(&self, contains: impl Fn(&[u8]) -> bool) -> Result<()> {
+        let mut present: HashMap<&[u8], bool> = HashMap::new();
+        for cmd in &self.cmds {
+            match cmd {
+                Command::Set { key, .. } => {
+                    present.insert(key, true);
+                }
+                Command::Remove { key, .. } => {
+                    let exists = match present.get(key.as_slice()) {
+                        Some(&exists) => exists,
+                        None => contains(key),
+                    };
+                    if !exists {
+                        return Err(KvError::key_not_found(key));
+                    }
+                    present.insert(key, false);
+                }
+                Command::Batch(_) => unreachable!("batches do not nest"),
+            }
+        }
+        Ok(())
+    }
+
+    pub(crate) fn into_commands(self) -> Vec<Command> {
+        self.cmds
+    }
+}
@@ -0,0 +1,58 @@
+use kv::error::KvError;
+use kv::{KvsClient, Result};
+use std::net::SocketAddr;
+use std::process::exit;
+use structopt::StructOpt;
+
+const DEFAULT_ADDR: &str = "127.0.0.1:4000";
+
+#[derive(StructOpt, Debug)]
+#[structopt(name = "kvs-client", about = env!("CARGO_PKG_DESCRIPTION"))]
+struct Opt {
+    /// Server address, as IP:PORT
+    #[structopt(long, global = true, default_value = DEFAULT_ADDR)]
+    addr: SocketAddr,
+
+    #[structopt(subcommand)]
+    cmd: ClientCli,
+}
+
+#[derive(StructOpt, Debug)]
+enum ClientCli {
+    #[structopt(name = "get")]
+    Get { key: String },
+
+    #[structopt(name = "set")]
+    Set { key: String, value: String },
+
+    #[structopt(name = "rm")]
+    Remove { key: String },
+}
+
+fn main() {
+    let opt = Opt::from_args();
+    match run(opt) {
+        Ok(()) => exit(0),
+        Err(e @ KvError::KeyNotFound(_)) => {
+            println!("Key not found");
+            exit(e.exit_code())
+        }
+        Err(e) => {
+            eprintln!("{}", e);
+            exit(e.exit_code())
+        }
+    }
+}
+
+fn run(opt: Opt) -> Result<()> {
+    let mut client = KvsClient::connect(opt.addr)?;
+    match opt.cmd {
+        ClientCli::Get { key } => match client.get(key)? {
+            Some(value) => println!("{}", value),
+            None => println!("Key not found"),
+        },
+        ClientCli::Set { key, value } => client.set(key, value)?,
+        ClientCli::Remove { key } => client.remove(key)?,
+    }
+    Ok(())
+}
@@ -0,0 +1,48 @@
+use kv::engine::{select_engine, EngineKind};
+use kv::{KvStore, KvsEngine, KvsServer, MemStore, Result};
+use log::{error, info};
+use std::env::current_dir;
+use std::net::SocketAddr;
+use std::process::exit;
+use structopt::StructOpt;
+
+const DEFAULT_ADDR: &str = "127.0.0.1:4000";
+
+#[derive(StructOpt, Debug)]
+#[structopt(name = "kvs-server", about = env!("CARGO_PKG_DESCRIPTION"))]
+struct Opt {
+    /// Address to listen on, as IP:PORT
+    #[structopt(long, default_value = DEFAULT_ADDR)]
+    addr: SocketAddr,
+
+    /// Storage engine, either "kvs" or "memory"; defaults to the one the directory was written with
+    #[structopt(long)]
+    engine: Option<EngineKind>,
+}
+
+fn main() {
+    env_logger::Builder::from_env(env_logger::Env::default().default_filter_or("info")).init();
+    let opt = Opt::from_args();
+    if let Err(e) = run(opt) {
+        error!("{}", e);
+        exit(e.exit_code());
+    }
+}
+
+fn run(opt: Opt) -> Result<()> {
+    let path = current_dir()?;
+    let engine = select_engine(&path, opt.engine)?;
+
+    info!("kvs-server {}", env!("CARGO_PKG_VERSION"));
+    info!("Storage engine: {}", engine);
+    info!("Listening on {}", opt.addr);
+
+    match engine {
+        EngineKind::Kvs => serve(KvStore::open(&path)?, opt.addr),
+        EngineKind::Memory => serve(MemStore::new(), opt.addr),
+    }
+}
+
+fn serve<E: KvsEngine>(engine: E, addr: SocketAddr) -> Result<()> {
+    KvsServer::new(engine).run(addr)
+}
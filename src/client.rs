@@ -0,0 +1,51 @@
+use crate::error::Result;
+use crate::protocol::{Request, Response};
+use serde::Deserialize;
+use serde_json::de::IoRead;
+use serde_json::Deserializer;
+use std::io::{BufReader, BufWriter, Write};
+use std::net::{TcpStream, ToSocketAddrs};
+
+/**
+ * ! client side of the wire protocol, one TCP connection per client
+ */
+pub struct KvsClient {
+    reader: Deserializer<IoRead<BufReader<TcpStream>>>,
+    writer: BufWriter<TcpStream>,
+}
+
+impl KvsClient {
+    pub fn connect(addr: impl ToSocketAddrs) -> Result<KvsClient> {
+        let stream = TcpStream::connect(addr)?;
+        let reader = Deserializer::from_reader(BufReader::new(stream.try_clone()?));
+        Ok(KvsClient {
+            reader,
+            writer: BufWriter::new(stream),
+        })
+    }
+
+    pub fn get(&mut self, key: String) -> Result<Option<String>> {
+        self.request(&Request::Get { key })
+    }
+
+    pub fn set(&mut self, key: String, value: String) -> Result<()> {
+        self.request(&Request::Set { key, value }).map(|_| ())
+    }
+
+    pub fn remove(&mut self, key: String) -> Result<()> {
+        self.request(&Request::Remove { key }).map(|_| ())
+    }
+
+    /**
+     * ! send one request and wait for its response
+     * * a server side error is turned back into the matching `KvError`
+     */
+    fn request(&mut self, req: &Request) -> Result<Option<String>> {
+        serde_json::to_writer(&mut self.writer, req)?;
+        self.writer.flush()?;
+        match Response::deserialize(&mut self.reader)? {
+            Response::Ok(value) => Ok(value),
+            Response::Err(e) => Err(e.into()),
+        }
+    }
+}
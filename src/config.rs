@@ -0,0 +1,116 @@
+use crate::engine::EngineKind;
+use crate::error::{KvError, Result};
+use crate::options::{CompactionPolicy, Durability, Options};
+use serde::Deserialize;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+
+/**
+ * ! environment variable naming the data directory, `--data-dir` takes precedence
+ */
+pub const DATA_DIR_ENV: &str = "KV_DATA_DIR";
+
+/**
+ * ! environment variable naming the config file, `--config` takes precedence
+ */
+pub const CONFIG_ENV: &str = "KV_CONFIG";
+
+/**
+ * ! settings of the `kv` command line read from a TOML file, each one optional
+ * * a setting given on the command line or in the environment wins over the file,
+ * * the file wins over the defaults
+ * * ```toml
+ * * data_dir = "/var/lib/kv"
+ * * engine = "kvs"
+ * * durability = "periodic:100:1048576"
+ * * max_file_size = 67108864
+ * *
+ * * [compaction]
+ * * stale_bytes = 1048576
+ * * stale_ratio = 0.5
+ * * max_gens = 16
+ * * ```
+ * * `engine` and `durability` take the same values as the flags of the same name
+ * * a `[compaction]` table replaces the default policy, thresholds it leaves out are not
+ * * used, so an empty table only compacts on request
+ */
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Config {
+    pub data_dir: Option<PathBuf>,
+    pub engine: Option<EngineKind>,
+    pub durability: Option<Durability>,
+    pub compaction: Option<CompactionPolicy>,
+    pub max_file_size: Option<u64>,
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct ConfigFile {
+    data_dir: Option<PathBuf>,
+    engine: Option<String>,
+    durability: Option<String>,
+    compaction: Option<CompactionTable>,
+    max_file_size: Option<u64>,
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct CompactionTable {
+    stale_bytes: Option<u64>,
+    stale_ratio: Option<f64>,
+    max_gens: Option<usize>,
+}
+
+impl Config {
+    /**
+     * ! read the config file at `path`
+     * * a relative `data_dir` is taken relative to the directory of the file
+     */
+    pub fn load(path: &Path) -> Result<Config> {
+        let content = fs::read_to_string(path).map_err(|e| {
+            KvError::InvalidOption(format!("config file {}: {}", path.display(), e))
+        })?;
+        let mut config: Config = content.parse()?;
+        if let (Some(dir), Some(base)) = (config.data_dir.take(), path.parent()) {
+            config.data_dir = Some(base.join(dir));
+        }
+        Ok(config)
+    }
+
+    /**
+     * ! `Options::default()` with the settings of this file in place
+     */
+    pub fn options(&self) -> Options {
+        let defaults = Options::default();
+        Options {
+            durability: self.durability.unwrap_or(defaults.durability),
+            compaction: self.compaction.unwrap_or(defaults.compaction),
+            max_file_size: self.max_file_size.unwrap_or(defaults.max_file_size),
+        }
+    }
+}
+
+impl FromStr for Config {
+    type Err = KvError;
+
+    fn from_str(s: &str) -> Result<Config> {
+        let file: ConfigFile =
+            toml::from_str(s).map_err(|e| KvError::InvalidOption(format!("config file: {}", e)))?;
+        let compaction = file.compaction.map(|table| CompactionPolicy {
+            stale_bytes: table.stale_bytes,
+            stale_ratio: table.stale_ratio,
+            max_gens: table.max_gens,
+        });
+        if let Some(policy) = &compaction {
+            policy.validate()?;
+        }
+        Ok(Config {
+            data_dir: file.data_dir,
+            engine: file.engine.as_deref().map(str::parse).transpose()?,
+            durability: file.durability.as_deref().map(str::parse).transpose()?,
+            compaction,
+            max_file_size: file.max_file_size,
+        })
+    }
+}
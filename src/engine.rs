@@ -0,0 +1,300 @@
+use crate::batch::WriteBatch;
+use crate::error::{KvError, Result};
+use crate::manifest::MANIFEST;
+use crate::scan::{byte_range, Scan, StrScan};
+use std::fmt;
+use std::fs;
+use std::ops::{Bound, RangeBounds};
+use std::path::Path;
+use std::str::FromStr;
+use std::time::Duration;
+
+/**
+ * ! file recording which engine wrote a data directory
+ */
+const ENGINE_FILE: &str = "engine";
+
+/**
+ * ! common interface of the storage engines
+ * * the CLI and any service code should only talk to a store through this trait,
+ * * so the log-structured `KvStore` can be swapped for `MemStore` in tests
+ * * an engine is a cheap handle: clones share the same data and can be sent to other threads
+ * * keys and values are arbitrary bytes, the `String` methods are wrappers over the
+ * * byte methods for the common case of text
+ */
+pub trait KvsEngine: Clone + Send + Sync + 'static {
+    /**
+     * ! set the value of a key, overwriting any previous value
+     */
+    fn set_bytes(&self, key: &[u8], value: &[u8]) -> Result<()>;
+
+    /**
+     * ! set the value of a key that expires once `ttl` has passed, from then on it behaves
+     * ! as absent
+     */
+    fn set_with_ttl_bytes(&self, key: &[u8], value: &[u8], ttl: Duration) -> Result<()>;
+
+    /**
+     * ! get the value of a key, `None` if the key does not exist
+     */
+    fn get_bytes(&self, key: &[u8]) -> Result<Option<Vec<u8>>>;
+
+    /**
+     * ! remove a key, `KvError::KeyNotFound` if the key does not exist
+     */
+    fn remove_bytes(&self, key: &[u8]) -> Result<()>;
+
+    /**
+     * ! apply every set and remove of `batch`, or none of them if one of its removes
+     * ! fails with `KvError::KeyNotFound`
+     */
+    fn write(&self, batch: WriteBatch) -> Result<()>;
+
+    /**
+     * ! the pair with the smallest key in `range`, or the largest one if `reverse`,
+     * ! `None` if no key falls in `range`
+     */
+    fn seek_bytes(
+        &self,
+        range: (Bound<&[u8]>, Bound<&[u8]>),
+        reverse: bool,
+    ) -> Result<Option<(Vec<u8>, Vec<u8>)>>;
+
+    /**
+     * ! if the value of `key` is `expected`, replace it with `new`, and tell whether it was
+     * * `None` stands for an absent key on both sides, so `new: None` removes the key
+     * * the check and the write are atomic with respect to every other write of the engine
+     */
+    fn compare_and_swap_bytes(
+        &self,
+        key: &[u8],
+        expected: Option<&[u8]>,
+        new: Option<&[u8]>,
+    ) -> Result<bool>;
+
+    /**
+     * ! set `key` to `new` only if its value is `expected`
+     */
+    fn compare_and_set_bytes(&self, key: &[u8], expected: &[u8], new: &[u8]) -> Result<bool> {
+        self.compare_and_swap_bytes(key, Some(expected), Some(new))
+    }
+
+    /**
+     * ! set `key` to `value` only if the key does not exist
+     */
+    fn set_if_absent_bytes(&self, key: &[u8], value: &[u8]) -> Result<bool> {
+        self.compare_and_swap_bytes(key, None, Some(value))
+    }
+
+    /**
+     * ! remove `key` only if its value is `expected`, an absent key does not hold any value
+     */
+    fn remove_if_equals_bytes(&self, key: &[u8], expected: &[u8]) -> Result<bool> {
+        self.compare_and_swap_bytes(key, Some(expected), None)
+    }
+
+    /**
+     * ! iterate the key value pairs whose key falls in `range`, ordered by key bytes
+     */
+    fn scan_bytes<R: RangeBounds<Vec<u8>>>(&self, range: R) -> Scan<Self> {
+        Scan::new(self.clone(), range)
+    }
+
+    /**
+     * ! iterate the key value pairs whose key starts with `prefix`, ordered by key bytes
+     */
+    fn scan_prefix_bytes(&self, prefix: &[u8]) -> Scan<Self> {
+        Scan::prefix(self.clone(), prefix)
+    }
+
+    /**
+     * ! add `delta` to the integer stored as decimal text at `key` and return the new value
+     * * a missing key counts as 0, a value that is not an integer fails with
+     * * `KvError::NotAnInteger` and one that would leave the `i64` range with `KvError::Overflow`
+     * * the update retries `compare_and_swap_bytes` until no other write got in between,
+     * * the counter it writes has no expiry
+     */
+    fn incr_bytes(&self, key: &[u8], delta: i64) -> Result<i64> {
+        loop {
+            let current = self.get_bytes(key)?;
+            let value = match &current {
+                Some(value) => std::str::from_utf8(value)
+                    .ok()
+                    .and_then(|value| value.parse::<i64>().ok())
+                    .ok_or_else(|| {
+                        KvError::NotAnInteger(String::from_utf8_lossy(key).into_owned())
+                    })?,
+                None => 0,
+            };
+            let new = value
+                .checked_add(delta)
+                .ok_or_else(|| KvError::Overflow(String::from_utf8_lossy(key).into_owned()))?;
+            let text = new.to_string();
+            if self.compare_and_swap_bytes(key, current.as_deref(), Some(text.as_bytes()))? {
+                return Ok(new);
+            }
+        }
+    }
+
+    /**
+     * ! subtract `delta` from the integer at `key`, see `incr_bytes`
+     */
+    fn decr_bytes(&self, key: &[u8], delta: i64) -> Result<i64> {
+        let delta = delta
+            .checked_neg()
+            .ok_or_else(|| KvError::Overflow(String::from_utf8_lossy(key).into_owned()))?;
+        self.incr_bytes(key, delta)
+    }
+
+    fn set(&self, key: String, value: String) -> Result<()> {
+        self.set_bytes(key.as_bytes(), value.as_bytes())
+    }
+
+    fn set_with_ttl(&self, key: String, value: String, ttl: Duration) -> Result<()> {
+        self.set_with_ttl_bytes(key.as_bytes(), value.as_bytes(), ttl)
+    }
+
+    /**
+     * ! `KvError::NotUtf8` if the value was set through the byte methods and is not text
+     */
+    fn get(&self, key: String) -> Result<Option<String>> {
+        match self.get_bytes(key.as_bytes())? {
+            Some(value) => Ok(Some(String::from_utf8(value)?)),
+            None => Ok(None),
+        }
+    }
+
+    fn remove(&self, key: String) -> Result<()> {
+        self.remove_bytes(key.as_bytes())
+    }
+
+    fn compare_and_set(&self, key: String, expected: String, new: String) -> Result<bool> {
+        self.compare_and_set_bytes(key.as_bytes(), expected.as_bytes(), new.as_bytes())
+    }
+
+    fn set_if_absent(&self, key: String, value: String) -> Result<bool> {
+        self.set_if_absent_bytes(key.as_bytes(), value.as_bytes())
+    }
+
+    fn remove_if_equals(&self, key: String, expected: String) -> Result<bool> {
+        self.remove_if_equals_bytes(key.as_bytes(), expected.as_bytes())
+    }
+
+    fn incr(&self, key: String, delta: i64) -> Result<i64> {
+        self.incr_bytes(key.as_bytes(), delta)
+    }
+
+    fn decr(&self, key: String, delta: i64) -> Result<i64> {
+        self.decr_bytes(key.as_bytes(), delta)
+    }
+
+    /**
+     * ! UTF-8 sorts like the bytes it is made of, so the pairs are ordered by key as well
+     */
+    fn scan<R: RangeBounds<String>>(&self, range: R) -> StrScan<Self> {
+        StrScan::new(self.scan_bytes(byte_range(range)))
+    }
+
+    fn scan_prefix(&self, prefix: &str) -> StrScan<Self> {
+        StrScan::new(self.scan_prefix_bytes(prefix.as_bytes()))
+    }
+}
+
+/**
+ * ! the engines that can back a data directory
+ */
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum EngineKind {
+    Kvs,
+    Memory,
+}
+
+impl EngineKind {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            EngineKind::Kvs => "kvs",
+            EngineKind::Memory => "memory",
+        }
+    }
+}
+
+impl fmt::Display for EngineKind {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+impl FromStr for EngineKind {
+    type Err = KvError;
+
+    fn from_str(s: &str) -> Result<EngineKind> {
+        match s {
+            "kvs" => Ok(EngineKind::Kvs),
+            "memory" => Ok(EngineKind::Memory),
+            other => Err(KvError::UnknownEngine(other.to_owned())),
+        }
+    }
+}
+
+/**
+ * ! decide which engine to use for the data directory at `path`
+ * * the directory remembers the engine that first wrote it in the `engine` file
+ * * `requested` is the engine asked for by the caller, `None` means "whatever is there"
+ * * asking for a different engine than the recorded one is an error
+ * * a fresh directory records the chosen engine, defaulting to `kvs`
+ * * a directory without the file that holds logs or a manifest was written by `kvs`,
+ * * before the file existed or after it got lost
+ */
+pub fn select_engine(path: &Path, requested: Option<EngineKind>) -> Result<EngineKind> {
+    let kind = detect_engine(path, requested)?;
+    let marker = path.join(ENGINE_FILE);
+    if !marker.exists() {
+        fs::create_dir_all(path).map_err(|e| KvError::Io(e).at(path))?;
+        fs::write(&marker, kind.as_str()).map_err(|e| KvError::Io(e).at(&marker))?;
+    }
+    Ok(kind)
+}
+
+/**
+ * ! like `select_engine`, but a fresh directory is left as it is
+ */
+pub fn detect_engine(path: &Path, requested: Option<EngineKind>) -> Result<EngineKind> {
+    let marker = path.join(ENGINE_FILE);
+    let recorded = match fs::read_to_string(&marker) {
+        Ok(name) => Some(name.trim().parse::<EngineKind>()?),
+        Err(ref e) if e.kind() == std::io::ErrorKind::NotFound => None,
+        Err(e) => return Err(KvError::Io(e).at(&marker)),
+    };
+    let recorded = match recorded {
+        Some(kind) => Some(kind),
+        None if has_kvs_files(path)? => Some(EngineKind::Kvs),
+        None => None,
+    };
+
+    match (recorded, requested) {
+        (Some(found), Some(wanted)) if found != wanted => Err(KvError::WrongEngine {
+            expected: wanted.to_string(),
+            found: found.to_string(),
+        }),
+        (Some(found), _) => Ok(found),
+        (None, requested) => Ok(requested.unwrap_or(EngineKind::Kvs)),
+    }
+}
+
+/**
+ * ! whether `path` holds a log or the manifest of a `KvStore`, a missing directory holds neither
+ */
+fn has_kvs_files(path: &Path) -> Result<bool> {
+    let entries = match fs::read_dir(path) {
+        Ok(entries) => entries,
+        Err(ref e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(false),
+        Err(e) => return Err(KvError::Io(e).at(path)),
+    };
+    for entry in entries {
+        let file = entry.map_err(|e| KvError::Io(e).at(path))?.path();
+        if file.extension() == Some("log".as_ref()) || file.file_name() == Some(MANIFEST.as_ref()) {
+            return Ok(true);
+        }
+    }
+    Ok(false)
+}
@@ -1,22 +1,193 @@
+use std::error::Error;
+use std::fmt;
 use std::io;
-use failure_derive::Fail;
+use std::path::Path;
+use std::string::FromUtf8Error;
 
 /**
  * custom error type to indicate different error
  */
-#[derive(Fail, Debug)]
+#[derive(Debug)]
 pub enum KvError {
-    #[fail(display = "{}", _0)]
-    Io(#[cause] io::Error),
+    Io(io::Error),
 
-    #[fail(display = "{}", _0)]
-    Serde(#[cause] serde_json::Error),
+    /**
+     * ! an I/O error on the file or directory at `path`
+     */
+    File {
+        path: String,
+        source: io::Error,
+    },
 
-    #[fail(display = "Key not found")]
-    KeyNotFound,
+    Serde(serde_json::Error),
+
+    /**
+     * ! the key, lossily converted if it is not UTF-8
+     */
+    KeyNotFound(String),
 
-    #[fail(display = "Invalid command")]
     InvalidCommand,
+
+    /**
+     * ! reading the record at `offset` of generation `gen` failed
+     */
+    Log {
+        gen: u64,
+        offset: u64,
+        source: io::Error,
+    },
+
+    Corrupted {
+        gen: u64,
+        offset: u64,
+    },
+
+    InvalidManifest(String),
+
+    InvalidOption(String),
+
+    UnknownEngine(String),
+
+    WrongEngine {
+        expected: String,
+        found: String,
+    },
+
+    NotUtf8,
+
+    Conflict(String),
+
+    NotAnInteger(String),
+
+    Overflow(String),
+
+    KeyspaceNotFound(String),
+
+    KeyspaceExists(String),
+
+    InvalidKeyspace(String),
+
+    Locked(String),
+
+    ReadOnly,
+}
+
+impl KvError {
+    /**
+     * ! `KvError::KeyNotFound` for a key given as bytes
+     */
+    pub fn key_not_found(key: &[u8]) -> KvError {
+        KvError::KeyNotFound(String::from_utf8_lossy(key).into_owned())
+    }
+
+    /**
+     * ! a bare `KvError::Io` as `KvError::File` on `path`, other errors already tell
+     * ! where they happened and are left as they are
+     */
+    pub(crate) fn at(self, path: &Path) -> KvError {
+        match self {
+            KvError::Io(source) => KvError::File {
+                path: path.display().to_string(),
+                source,
+            },
+            e => e,
+        }
+    }
+
+    /**
+     * ! the status the command line tools exit with when they fail with this error
+     * * 1 is left to invalid arguments and 2 to a conditional write whose condition did not hold
+     * *  3 KeyNotFound        10 InvalidOption      17 KeyspaceNotFound
+     * *  4 Io                 11 UnknownEngine      18 KeyspaceExists
+     * *  5 Serde              12 WrongEngine        19 InvalidKeyspace
+     * *  6 Log                13 NotUtf8            20 Locked
+     * *  7 Corrupted          14 Conflict           21 ReadOnly
+     * *  8 InvalidManifest    15 NotAnInteger       22 File
+     * *  9 InvalidCommand     16 Overflow
+     * * the codes are stable, a new variant gets the next free one
+     * * `kv --help` lists them too, keep it in step
+     */
+    pub fn exit_code(&self) -> i32 {
+        match self {
+            KvError::KeyNotFound(_) => 3,
+            KvError::Io(_) => 4,
+            KvError::Serde(_) => 5,
+            KvError::Log { .. } => 6,
+            KvError::Corrupted { .. } => 7,
+            KvError::InvalidManifest(_) => 8,
+            KvError::InvalidCommand => 9,
+            KvError::InvalidOption(_) => 10,
+            KvError::UnknownEngine(_) => 11,
+            KvError::WrongEngine { .. } => 12,
+            KvError::NotUtf8 => 13,
+            KvError::Conflict(_) => 14,
+            KvError::NotAnInteger(_) => 15,
+            KvError::Overflow(_) => 16,
+            KvError::KeyspaceNotFound(_) => 17,
+            KvError::KeyspaceExists(_) => 18,
+            KvError::InvalidKeyspace(_) => 19,
+            KvError::Locked(_) => 20,
+            KvError::ReadOnly => 21,
+            KvError::File { .. } => 22,
+        }
+    }
+}
+
+impl fmt::Display for KvError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            KvError::Io(e) => write!(f, "{}", e),
+            KvError::Serde(e) => write!(f, "{}", e),
+            KvError::File { path, source } => write!(f, "{}: {}", path, source),
+            KvError::KeyNotFound(key) => write!(f, "Key not found: {}", key),
+            KvError::InvalidCommand => write!(f, "Invalid command"),
+            KvError::Log {
+                gen,
+                offset,
+                source,
+            } => write!(
+                f,
+                "Failed to read generation {} at offset {}: {}",
+                gen, offset, source
+            ),
+            KvError::Corrupted { gen, offset } => write!(
+                f,
+                "Corrupted record in generation {} at offset {}",
+                gen, offset
+            ),
+            KvError::InvalidManifest(line) => write!(f, "Invalid manifest entry: {}", line),
+            KvError::InvalidOption(msg) => write!(f, "Invalid option: {}", msg),
+            KvError::UnknownEngine(name) => write!(f, "Unknown engine: {}", name),
+            KvError::WrongEngine { expected, found } => write!(
+                f,
+                "Wrong engine: asked for {}, directory was written by {}",
+                expected, found
+            ),
+            KvError::NotUtf8 => write!(f, "Key or value is not valid UTF-8"),
+            KvError::Conflict(key) => write!(f, "Transaction conflict on key {}", key),
+            KvError::NotAnInteger(key) => write!(f, "Value of key {} is not an integer", key),
+            KvError::Overflow(key) => write!(f, "Counter {} would overflow", key),
+            KvError::KeyspaceNotFound(name) => write!(f, "Keyspace not found: {}", name),
+            KvError::KeyspaceExists(name) => write!(f, "Keyspace already exists: {}", name),
+            KvError::InvalidKeyspace(name) => write!(f, "Invalid keyspace: {}", name),
+            KvError::Locked(path) => {
+                write!(f, "Data directory {} is in use by another process", path)
+            }
+            KvError::ReadOnly => write!(f, "Store is open read-only"),
+        }
+    }
+}
+
+impl Error for KvError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            KvError::Io(e) | KvError::File { source: e, .. } | KvError::Log { source: e, .. } => {
+                Some(e)
+            }
+            KvError::Serde(e) => Some(e),
+            _ => None,
+        }
+    }
 }
 
 impl From<io::Error> for KvError {
@@ -31,4 +202,10 @@ impl From<serde_json::Error> for KvError {
     }
 }
 
+impl From<FromUtf8Error> for KvError {
+    fn from(_: FromUtf8Error) -> KvError {
+        KvError::NotUtf8
+    }
+}
+
 pub type Result<T> = std::result::Result<T, KvError>;
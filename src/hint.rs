@@ -0,0 +1,118 @@
+use crate::error::{KvError, Result};
+use crate::manifest::write_atomic;
+use crate::record::FORMAT_VERSION;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+
+/**
+ * ! hint files sit next to compacted generations and list where each key of the log lives
+ * * layout: magic, format version (u8), gen (u64 LE), log length (u64 LE), then per key
+ * * keyspace id (u32 LE), key length (u32 LE), key, pos (u64 LE), len (u64 LE), expiry (u64 LE, 0 for none),
+ * * and a crc32 (u32 LE) of all of it
+ * * a compacted log is never appended to, so the hint stays valid as long as the log length
+ * * and format it was written for still match
+ */
+const HINT_MAGIC: &[u8; 4] = b"KVHT";
+const HINT_HEADER_LEN: usize = HINT_MAGIC.len() + 1 + 8 + 8;
+
+/**
+ * ! one entry of a hint file, the record of `key` in `keyspace` sits at `pos..pos + len`
+ * ! of the log and expires at `expires`, like the record says
+ */
+pub(crate) struct Hint {
+    pub keyspace: u32,
+    pub key: Vec<u8>,
+    pub pos: u64,
+    pub len: u64,
+    pub expires: Option<u64>,
+}
+
+pub(crate) fn hint_path(path: &Path, gen: u64) -> PathBuf {
+    path.join(format!("{}.hint", gen))
+}
+
+pub(crate) fn write_hint(path: &Path, gen: u64, log_len: u64, hints: &[Hint]) -> Result<()> {
+    let mut buf = Vec::with_capacity(HINT_HEADER_LEN);
+    buf.extend_from_slice(HINT_MAGIC);
+    buf.push(FORMAT_VERSION);
+    buf.extend_from_slice(&gen.to_le_bytes());
+    buf.extend_from_slice(&log_len.to_le_bytes());
+    for hint in hints {
+        buf.extend_from_slice(&hint.keyspace.to_le_bytes());
+        buf.extend_from_slice(&(hint.key.len() as u32).to_le_bytes());
+        buf.extend_from_slice(&hint.key);
+        buf.extend_from_slice(&hint.pos.to_le_bytes());
+        buf.extend_from_slice(&hint.len.to_le_bytes());
+        buf.extend_from_slice(&hint.expires.unwrap_or(0).to_le_bytes());
+    }
+    let crc = crc32fast::hash(&buf);
+    buf.extend_from_slice(&crc.to_le_bytes());
+    write_atomic(&hint_path(path, gen), &buf)
+}
+
+/**
+ * ! read the hint file of gen, `None` if there is none or it does not describe a log of `log_len`
+ */
+pub(crate) fn read_hint(path: &Path, gen: u64, log_len: u64) -> Result<Option<Vec<Hint>>> {
+    let file = hint_path(path, gen);
+    let content = match fs::read(&file) {
+        Ok(content) => content,
+        Err(ref e) if e.kind() == io::ErrorKind::NotFound => return Ok(None),
+        Err(e) => return Err(KvError::Io(e).at(&file)),
+    };
+    Ok(parse_hint(&content, gen, log_len))
+}
+
+fn parse_hint(content: &[u8], gen: u64, log_len: u64) -> Option<Vec<Hint>> {
+    if content.len() < HINT_HEADER_LEN + 4 {
+        return None;
+    }
+    let (body, crc) = content.split_at(content.len() - 4);
+    if crc32fast::hash(body).to_le_bytes() != crc {
+        return None;
+    }
+    let (header, mut entries) = body.split_at(HINT_HEADER_LEN);
+    if &header[..4] != HINT_MAGIC
+        || header[4] != FORMAT_VERSION
+        || read_u64(&header[5..13]) != gen
+        || read_u64(&header[13..21]) != log_len
+    {
+        return None;
+    }
+
+    let mut hints = Vec::new();
+    while !entries.is_empty() {
+        if entries.len() < 8 {
+            return None;
+        }
+        let keyspace = read_u32(&entries[..4]);
+        let key_len = read_u32(&entries[4..8]) as usize;
+        if entries.len() < 8 + key_len + 24 {
+            return None;
+        }
+        let key = entries[8..8 + key_len].to_vec();
+        let rest = &entries[8 + key_len..];
+        hints.push(Hint {
+            keyspace,
+            key,
+            pos: read_u64(&rest[..8]),
+            len: read_u64(&rest[8..16]),
+            expires: Some(read_u64(&rest[16..24])).filter(|&expires| expires != 0),
+        });
+        entries = &rest[24..];
+    }
+    Some(hints)
+}
+
+fn read_u32(bytes: &[u8]) -> u32 {
+    let mut buf = [0; 4];
+    buf.copy_from_slice(bytes);
+    u32::from_le_bytes(buf)
+}
+
+fn read_u64(bytes: &[u8]) -> u64 {
+    let mut buf = [0; 8];
+    buf.copy_from_slice(bytes);
+    u64::from_le_bytes(buf)
+}
@@ -0,0 +1,83 @@
+use crate::error::{KvError, Result};
+use crate::manifest::write_atomic;
+use crate::record::DEFAULT_KEYSPACE_ID;
+use std::fs;
+use std::io;
+use std::path::Path;
+
+/**
+ * ! the KEYSPACES file names the keyspaces of a directory besides the default one
+ * * the first line is the id the next keyspace gets, then one "<id> <name>" per line,
+ * * replaced atomically like the manifest
+ * * ids are never reused, so records of a dropped keyspace that are still in the logs
+ * * belong to no keyspace on replay and are skipped, until compaction drops them
+ * * a directory without the file only has the default keyspace
+ */
+const KEYSPACES: &str = "KEYSPACES";
+
+/**
+ * ! name of the keyspace every store has and that cannot be dropped
+ */
+pub(crate) const DEFAULT_KEYSPACE: &str = "default";
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) struct KeyspaceList {
+    pub next_id: u32,
+    pub keyspaces: Vec<(u32, String)>,
+}
+
+impl Default for KeyspaceList {
+    fn default() -> KeyspaceList {
+        KeyspaceList {
+            next_id: DEFAULT_KEYSPACE_ID + 1,
+            keyspaces: Vec::new(),
+        }
+    }
+}
+
+impl KeyspaceList {
+    pub(crate) fn read(path: &Path) -> Result<KeyspaceList> {
+        let file = path.join(KEYSPACES);
+        let content = match fs::read_to_string(&file) {
+            Ok(content) => content,
+            Err(ref e) if e.kind() == io::ErrorKind::NotFound => return Ok(KeyspaceList::default()),
+            Err(e) => return Err(KvError::Io(e).at(&file)),
+        };
+        let invalid = |line: &str| KvError::InvalidManifest(line.to_owned());
+        let mut lines = content.lines().filter(|line| !line.is_empty());
+        let next_id = match lines.next() {
+            Some(line) => line.trim().parse::<u32>().map_err(|_| invalid(line))?,
+            None => return Err(invalid("")),
+        };
+        let keyspaces = lines
+            .map(|line| {
+                let (id, name) = line.split_once(' ').ok_or_else(|| invalid(line))?;
+                let id = id.parse::<u32>().map_err(|_| invalid(line))?;
+                Ok((id, name.to_owned()))
+            })
+            .collect::<Result<Vec<_>>>()?;
+        Ok(KeyspaceList { next_id, keyspaces })
+    }
+
+    /**
+     * ! atomically replace the list, the new one is durable when this returns
+     */
+    pub(crate) fn write(&self, path: &Path) -> Result<()> {
+        let mut content = format!("{}\n", self.next_id);
+        for (id, name) in &self.keyspaces {
+            content.push_str(&format!("{} {}\n", id, name));
+        }
+        write_atomic(&path.join(KEYSPACES), content.as_bytes())
+    }
+}
+
+/**
+ * ! `KvError::InvalidKeyspace` unless `name` can name a new keyspace
+ * * one line of the KEYSPACES file holds the name, and the default keyspace always exists
+ */
+pub(crate) fn check_name(name: &str) -> Result<()> {
+    if name.is_empty() || name.contains(['\n', '\r']) || name == DEFAULT_KEYSPACE {
+        return Err(KvError::InvalidKeyspace(name.to_owned()));
+    }
+    Ok(())
+}
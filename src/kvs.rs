@@ -1,30 +1,253 @@
+use crate::batch::WriteBatch;
+use crate::engine::KvsEngine;
 use crate::error::{KvError, Result};
-use serde::{Deserialize, Serialize};
+use crate::hint::{hint_path, read_hint, write_hint, Hint};
+use crate::keyspace::{check_name, KeyspaceList, DEFAULT_KEYSPACE};
+use crate::lock::DirLock;
+use crate::manifest::{sync_dir, tmp_path, Manifest};
+use crate::options::{CompactionPolicy, Durability, Options};
+use crate::record::{
+    decode, expiry, now_millis, write_file_header, Command, JsonCommand, LogFormat, RecordReader,
+    DEFAULT_KEYSPACE_ID, FILE_HEADER_LEN, MAGIC, RECORD_OVERHEAD,
+};
+use crate::scan::{Scan, StrScan};
+use crate::snapshot::Snapshot;
+use crate::transaction::Transaction;
+use crossbeam_skiplist::SkipMap;
+use crossbeam_utils::atomic::AtomicCell;
+use log::{error, info, warn};
 use serde_json::Deserializer;
-use std::collections::BTreeMap;
-use std::collections::HashMap;
+use std::collections::{BTreeMap, HashMap, HashSet};
 use std::ffi::OsStr;
 use std::fs::{File, OpenOptions};
 use std::io;
 use std::io::{BufReader, BufWriter, Read, Seek, SeekFrom, Write};
+use std::iter;
+use std::ops::{Bound, RangeBounds};
 use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::{Arc, Mutex, Weak};
+use std::thread::{self, JoinHandle};
+use std::time::{Duration, Instant};
 
-const COMPACTION_THRESHOLD: u64 = 1024 * 1024;
-
+/**
+ * ! a cloneable handle to a log-structured store
+ * * every clone shares the same index, file handles and writer, so a handle can be
+ * * cloned into as many threads as needed
+ * * gets never take a lock: the index is a lock-free SkipMap and values are read
+ * * with positional reads on shared file handles, so no reader seeks a shared cursor
+ * * sets and removes are serialized by the writer Mutex
+ * * a handle reads and writes one keyspace, `KvStore::open` returns the default one,
+ * * `KvStore::keyspace` a handle to another keyspace of the same store
+ */
+#[derive(Clone)]
 pub struct KvStore {
-    writer: BufWriterWithPos,
-    index: BTreeMap<String, CommandPos>,
-    readers: HashMap<u64, BufReaderWithPos>,
-    curr_gen: u64,
-    compaction: u64,
-    path: PathBuf,
+    index: Arc<Index>,
+    keyspaces: Arc<Keyspaces>,
+    reader: KvStoreReader,
+    writer: Arc<Mutex<KvStoreWriter>>,
+    points: Arc<ReadPoints>,
 }
 
-#[derive(Debug, Clone, Copy)]
+/**
+ * ! where a record is, and when the key it sets expires if it does
+ */
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
 struct CommandPos {
     gen: u64,
     pos: u64,
     len: u64,
+    expires: Option<u64>,
+}
+
+impl CommandPos {
+    /**
+     * ! whether the key still exists, an expired key is only dropped by the next compaction
+     */
+    fn is_live(&self) -> bool {
+        self.expires.is_none_or(|expires| expires > now_millis())
+    }
+}
+
+/**
+ * ! in-memory index of one keyspace, from key to the position of its latest Set command
+ * * SkipMap::insert on an existing key unlinks the old entry before linking the new one,
+ * * so a concurrent get could miss the key in between
+ * * an existing entry is therefore updated in place through its AtomicCell
+ * * only the writer adds and removes keys, so the get-then-insert below never races
+ * * background compaction only swaps positions of existing entries, see `replace`
+ */
+struct Index {
+    id: u32,
+    name: String,
+    map: SkipMap<Vec<u8>, AtomicCell<CommandPos>>,
+    // ! bytes of the records the entries point to
+    bytes: AtomicU64,
+    dropped: AtomicBool,
+}
+
+impl Index {
+    fn new(id: u32, name: &str) -> Index {
+        Index {
+            id,
+            name: name.to_owned(),
+            map: SkipMap::new(),
+            bytes: AtomicU64::new(0),
+            dropped: AtomicBool::new(false),
+        }
+    }
+
+    /**
+     * ! `KvError::KeyspaceNotFound` once the keyspace was dropped
+     * * a handle can outlive the drop, its index then points into gens compaction deletes
+     */
+    fn check(&self) -> Result<()> {
+        if self.dropped.load(Ordering::SeqCst) {
+            return Err(KvError::KeyspaceNotFound(self.name.clone()));
+        }
+        Ok(())
+    }
+
+    fn get(&self, key: &[u8]) -> Option<CommandPos> {
+        self.map.get(key).map(|entry| entry.value().load())
+    }
+
+    /**
+     * ! whether `key` exists and has not expired
+     */
+    fn contains_key(&self, key: &[u8]) -> bool {
+        self.get(key).is_some_and(|pos| pos.is_live())
+    }
+
+    fn insert(&self, key: Vec<u8>, pos: CommandPos) {
+        self.bytes.fetch_add(pos.len, Ordering::SeqCst);
+        match self.map.get(key.as_slice()) {
+            Some(entry) => {
+                let old = entry.value().swap(pos);
+                self.bytes.fetch_sub(old.len, Ordering::SeqCst);
+            }
+            None => {
+                self.map.insert(key, AtomicCell::new(pos));
+            }
+        }
+    }
+
+    fn remove(&self, key: &[u8]) -> Option<CommandPos> {
+        let old = self.map.remove(key).map(|entry| entry.value().load())?;
+        self.bytes.fetch_sub(old.len, Ordering::SeqCst);
+        Some(old)
+    }
+
+    /**
+     * ! point `key` at `new`, unless it was written or removed since it was at `old`
+     */
+    fn replace(&self, key: &[u8], old: CommandPos, new: CommandPos) {
+        if let Some(entry) = self.map.get(key) {
+            if entry.value().compare_exchange(old, new).is_ok() {
+                self.bytes.fetch_add(new.len, Ordering::SeqCst);
+                self.bytes.fetch_sub(old.len, Ordering::SeqCst);
+            }
+        }
+    }
+
+    fn bytes(&self) -> u64 {
+        self.bytes.load(Ordering::SeqCst)
+    }
+
+    fn range<'a, R: RangeBounds<[u8]> + 'a>(
+        &'a self,
+        range: R,
+    ) -> impl DoubleEndedIterator<Item = (Vec<u8>, CommandPos)> + 'a {
+        self.map
+            .range::<[u8], R>(range)
+            .map(|entry| (entry.key().clone(), entry.value().load()))
+    }
+}
+
+/**
+ * ! the keyspaces of a store, each with its own index, see `KvStore::keyspace`
+ * * only the writer creates and drops keyspaces, while it holds its Mutex
+ */
+struct Keyspaces {
+    default: Arc<Index>,
+    named: Mutex<BTreeMap<String, Arc<Index>>>,
+}
+
+impl Keyspaces {
+    fn get(&self, name: &str) -> Option<Arc<Index>> {
+        if name == DEFAULT_KEYSPACE {
+            return Some(Arc::clone(&self.default));
+        }
+        self.named.lock().unwrap().get(name).cloned()
+    }
+
+    fn all(&self) -> Vec<Arc<Index>> {
+        let named = self.named.lock().unwrap();
+        let mut all = vec![Arc::clone(&self.default)];
+        all.extend(named.values().cloned());
+        all
+    }
+}
+
+/**
+ * ! the store as it was when a snapshot was taken
+ * * the first time the writer changes a key afterwards, it saves the position the key had
+ * * before, `None` if it did not exist, so reads keep resolving the older record
+ * * the writer saves the old position before it touches the index, so a reader that finds
+ * * nothing saved after looking the key up in the index read the right position
+ * * a read point belongs to the keyspace it was taken in, writes to others do not touch it
+ */
+pub(crate) struct ReadPoint {
+    keyspace: u32,
+    before: SkipMap<Vec<u8>, Option<CommandPos>>,
+}
+
+impl ReadPoint {
+    /**
+     * ! whether `key` was set or removed since the read point was taken
+     */
+    pub(crate) fn changed(&self, key: &[u8]) -> bool {
+        self.before.contains_key(key)
+    }
+}
+
+/**
+ * ! every live read point of a store
+ * * compaction keeps the gens that saved positions point into, holding the lock while it
+ * * decides, so no position can be saved into a gen it is about to delete
+ * * a gen kept this way is deleted by the first compaction after its read points are gone
+ */
+#[derive(Default)]
+struct ReadPoints {
+    points: Mutex<Vec<Weak<ReadPoint>>>,
+}
+
+impl ReadPoints {
+    fn register(&self, keyspace: u32) -> Arc<ReadPoint> {
+        let point = Arc::new(ReadPoint {
+            keyspace,
+            before: SkipMap::new(),
+        });
+        self.points.lock().unwrap().push(Arc::downgrade(&point));
+        point
+    }
+
+    /**
+     * ! save where `key` is now in every read point that has not seen it change yet
+     */
+    fn save(&self, index: &Index, key: &[u8]) {
+        let mut points = self.points.lock().unwrap();
+        points.retain(|point| point.strong_count() > 0);
+        if points.is_empty() {
+            return;
+        }
+        let pos = index.get(key);
+        for point in points.iter().filter_map(Weak::upgrade) {
+            if point.keyspace == index.id {
+                point.before.get_or_insert(key.to_vec(), pos);
+            }
+        }
+    }
 }
 
 #[derive(Debug)]
@@ -35,11 +258,15 @@ struct BufWriterWithPos {
 
 impl BufWriterWithPos {
     fn new(mut inner: BufWriter<File>) -> Result<Self> {
-        let pos = inner.seek(SeekFrom::Current(0))?;
-        Ok(BufWriterWithPos {
-            writer: inner,
-            pos: pos,
-        })
+        // ! a file opened for appending only moves to its end with the first write
+        let pos = inner.seek(SeekFrom::End(0))?;
+        Ok(BufWriterWithPos { writer: inner, pos })
+    }
+
+    fn sync_data(&mut self) -> Result<()> {
+        self.writer.flush()?;
+        self.writer.get_ref().sync_data()?;
+        Ok(())
     }
 }
 
@@ -51,7 +278,7 @@ struct BufReaderWithPos {
 
 impl BufReaderWithPos {
     fn new(mut reader: BufReader<File>) -> Result<Self> {
-        let pos = reader.seek(SeekFrom::Current(0))?;
+        let pos = reader.stream_position()?;
         Ok(BufReaderWithPos { reader, pos })
     }
 }
@@ -92,41 +319,210 @@ impl Seek for BufReaderWithPos {
 }
 
 /**
- * ! Command that used to serialize
+ * ! read side of the store, shared by every clone of a KvStore
+ * * one File per generation, opened lazily and read with positional reads,
+ * * so any number of threads can read the same generation at once
+ * * compaction drops the handles of stale generations from the map, a reader still
+ * * holding one keeps reading the unlinked file until it lets go of it
+ */
+#[derive(Clone)]
+struct KvStoreReader {
+    path: Arc<PathBuf>,
+    files: Arc<SkipMap<u64, Arc<LogFile>>>,
+}
+
+/**
+ * ! an open log file together with the record format it was written in
+ */
+struct LogFile {
+    file: File,
+    format: LogFormat,
+}
+
+impl KvStoreReader {
+    fn file(&self, gen: u64) -> Result<Arc<LogFile>> {
+        if let Some(entry) = self.files.get(&gen) {
+            return Ok(entry.value().clone());
+        }
+        let file = File::open(log_path(&self.path, gen))?;
+        let mut head = vec![0; file.metadata()?.len().min(FILE_HEADER_LEN) as usize];
+        read_exact_at(&file, &mut head, 0)?;
+        let format = LogFormat::detect(&head).ok_or(KvError::Corrupted {
+            gen,
+            offset: MAGIC.len() as u64,
+        })?;
+        let log_file = Arc::new(LogFile { file, format });
+        Ok(self.files.get_or_insert(gen, log_file).value().clone())
+    }
+
+    /**
+     * ! read and decode the command stored at `pos`, verifying its checksum
+     */
+    fn read_command(&self, pos: CommandPos) -> Result<Command> {
+        let log_file = self.file(pos.gen)?;
+        let mut buf = vec![0; pos.len as usize];
+        read_exact_at(&log_file.file, &mut buf, pos.pos).map_err(|source| KvError::Log {
+            gen: pos.gen,
+            offset: pos.pos,
+            source,
+        })?;
+        decode(log_file.format, &buf, pos.gen, pos.pos)
+    }
+
+    /**
+     * ! read the value of the Set command stored at `pos`
+     */
+    fn read_value(&self, pos: CommandPos) -> Result<Vec<u8>> {
+        if let Command::Set { value, .. } = self.read_command(pos)? {
+            Ok(value)
+        } else {
+            Err(KvError::InvalidCommand)
+        }
+    }
+
+    fn close_stale_files(&self, safe_point: u64) {
+        while let Some(entry) = self.files.front() {
+            if *entry.key() >= safe_point {
+                break;
+            }
+            entry.remove();
+        }
+    }
+}
+
+/**
+ * ! write side of the store, only ever used behind the Mutex in KvStore
  */
-#[derive(Serialize, Deserialize, Debug)]
-enum Command {
-    Set { key: String, value: String },
-    Remove { key: String },
+struct KvStoreWriter {
+    reader: KvStoreReader,
+    // ! the active gen, a read-only store has none
+    writer: Option<BufWriterWithPos>,
+    keyspaces: Arc<Keyspaces>,
+    // ! id of the next keyspace created, ids are never reused
+    next_keyspace: u32,
+    points: Arc<ReadPoints>,
+    curr_gen: u64,
+    path: Arc<PathBuf>,
+    durability: Durability,
+    policy: CompactionPolicy,
+    max_file_size: u64,
+    // ! record bytes in all gens, how many of them are stale, and the number of gen files
+    total: u64,
+    stale: u64,
+    gens: usize,
+    // ! bytes flushed to the active gen since its last fsync
+    unsynced: u64,
+    last_sync: Instant,
+    // ! the background compaction started last, if it was not joined yet
+    compaction_thread: Option<CompactionThread>,
+    read_only: bool,
+    // ! released once everything else is closed
+    _lock: DirLock,
+}
+
+/**
+ * ! a background compaction and the counts of the writer when it started
+ * * the thread returns the record bytes it copied, once it succeeded the gens counted
+ * * in `total`, `stale` and `gens` are replaced by the compacted one
+ */
+struct CompactionThread {
+    thread: JoinHandle<Result<u64>>,
+    total: u64,
+    stale: u64,
+    gens: usize,
+}
+
+/**
+ * ! a compaction running on a background thread
+ * * `entries` are the indexes of all keyspaces as they were when the writer switched to
+ * * `active_gen`, every record they point to lives in a gen older than `gen` and is
+ * * copied to `gen`
+ */
+struct Compaction {
+    reader: KvStoreReader,
+    points: Arc<ReadPoints>,
+    path: Arc<PathBuf>,
+    gen: u64,
+    active_gen: u64,
+    entries: Vec<(Arc<Index>, Vec<u8>, CommandPos)>,
 }
 
 impl KvStore {
     /**
      * ! impl {kv get key},
-     * ! 1. read the entire log
-     * ! 2. check if key is in index (index is like a cache of all inserted Command)
-     * ! 3. if key is not in the index, report KeyNotFound directly
-     * ! 4. get the reader from readers map through gen
-     * ! 5. seek the reader to the position of the file, and take only at the length of len
-     * ! 6. deserialize with serde::from_reader, return the value
-     */
-    pub fn get(&mut self, key: String) -> Result<Option<String>> {
-
-        if let Some(pos) = self.index.get(&key) {
-            let reader = self
-                .readers
-                .get_mut(&pos.gen)
-                .expect("cannot find the log reader");
-            reader.seek(SeekFrom::Start(pos.pos))?;
-            let reader = reader.take(pos.len);
-            if let Command::Set { value, .. } = serde_json::from_reader(reader)? {
-                Ok(Some(value))
+     * ! 1. check if key is in index (index is like a cache of all inserted Command)
+     * ! 2. if key is not in the index, return None directly
+     * ! 3. read len bytes at the position of the log file of gen
+     * ! 4. decode the record, return the value
+     */
+    pub fn get_bytes(&self, key: &[u8]) -> Result<Option<Vec<u8>>> {
+        self.index.check()?;
+        match self.index.get(key).filter(CommandPos::is_live) {
+            Some(pos) => self.read_value(key, pos),
+            None => Ok(None),
+        }
+    }
+
+    /**
+     * ! impl {kv scan}
+     * * the index is ordered by key, so a scan steps from key to key through it,
+     * * see `Scan`
+     */
+    pub fn scan_bytes<R: RangeBounds<Vec<u8>>>(&self, range: R) -> Scan<KvStore> {
+        KvsEngine::scan_bytes(self, range)
+    }
+
+    pub fn scan_prefix_bytes(&self, prefix: &[u8]) -> Scan<KvStore> {
+        KvsEngine::scan_prefix_bytes(self, prefix)
+    }
+
+    /**
+     * ! the first key of `range` from the front or the back with its value
+     * * a key removed between the index lookup and the read is skipped
+     */
+    fn seek(
+        &self,
+        (start, end): (Bound<&[u8]>, Bound<&[u8]>),
+        reverse: bool,
+    ) -> Result<Option<(Vec<u8>, Vec<u8>)>> {
+        self.index.check()?;
+        let mut skipped: Option<Vec<u8>> = None;
+        loop {
+            let past = skipped.as_deref().map(Bound::Excluded);
+            let range = if reverse {
+                (start, past.unwrap_or(end))
             } else {
-                Err(KvError::InvalidCommand)
+                (past.unwrap_or(start), end)
+            };
+            let live = self.index.range(range).filter(|(_, pos)| pos.is_live());
+            let (key, pos) = match first(live, reverse) {
+                Some(entry) => entry,
+                None => return Ok(None),
+            };
+            match self.read_value(&key, pos)? {
+                Some(value) => return Ok(Some((key, value))),
+                None => skipped = Some(key),
+            }
+        }
+    }
+
+    /**
+     * ! read the value of `key` that the index placed at `pos`
+     * * a concurrent compaction may have moved the key and deleted the generation
+     * * `pos` points to, in that case look the key up again and retry
+     */
+    fn read_value(&self, key: &[u8], mut pos: CommandPos) -> Result<Option<Vec<u8>>> {
+        loop {
+            match self.reader.read_value(pos) {
+                Err(KvError::Io(ref e)) if e.kind() == io::ErrorKind::NotFound => {
+                    match self.index.get(key) {
+                        Some(new_pos) if new_pos != pos => pos = new_pos,
+                        Some(_) => return self.reader.read_value(pos).map(Some),
+                        None => return Ok(None),
+                    }
+                }
+                res => return res.map(Some),
             }
-        } else {
-            // Err(KvError::KeyNotFound)
-            Ok(None)
         }
     }
 
@@ -136,172 +532,1130 @@ impl KvStore {
      * ! serialize the Command structure into the offset of that file
      * ! insert the (key, CommandPos) pair into index
      */
-    pub fn set(&mut self, key: String, value: String) -> Result<()> {
-        let cmd = Command::Set {
-            key: key.clone(),
-            value,
-        };
-
-        let pos = self.writer.pos;
+    pub fn set_bytes(&self, key: &[u8], value: &[u8]) -> Result<()> {
+        self.writer
+            .lock()
+            .unwrap()
+            .set(&self.index, key, value, None)
+    }
 
-        serde_json::to_writer(&mut self.writer, &cmd)?;
-        self.writer.flush()?;
+    /**
+     * ! set a key that behaves as absent once `ttl` has passed
+     * * the expiry time is stored in the record, so it holds across `open`,
+     * * the expired record is dropped by the next compaction
+     */
+    pub fn set_with_ttl_bytes(&self, key: &[u8], value: &[u8], ttl: Duration) -> Result<()> {
+        self.writer
+            .lock()
+            .unwrap()
+            .set(&self.index, key, value, Some(expiry(ttl)))
+    }
 
-        // ! insert a (key, CommandPos) pair into index as a cache in memory
-        // ! the index is implemented with a BTreeMap
-        self.index.insert(
-            key,
-            CommandPos {
-                gen: self.curr_gen,
-                pos,
-                len: self.writer.pos - pos,
-            },
-        );
+    /**
+     * ! impl {kv remove key}\n
+     * ! 1. check the key in the index\n
+     * ! 2. if the key presents, serialize a Remove command and drop it from the index
+     */
+    pub fn remove_bytes(&self, key: &[u8]) -> Result<()> {
+        self.writer.lock().unwrap().remove(&self.index, key)
+    }
 
-        self.compaction += self.writer.pos - pos;
+    /**
+     * ! apply every set and remove of `batch` atomically, see `WriteBatch`
+     */
+    pub fn write(&self, batch: WriteBatch) -> Result<()> {
+        self.writer.lock().unwrap().write(&self.index, batch)
+    }
 
-        if self.compaction >= COMPACTION_THRESHOLD {
-            self.compaction()?;
+    /**
+     * ! if the value of `key` is `expected`, replace it with `new`, and tell whether it was
+     * * `None` stands for an absent key, see `KvsEngine::compare_and_swap_bytes`
+     * * the value is checked under the writer lock, so no other write can slip in between
+     */
+    pub fn compare_and_swap_bytes(
+        &self,
+        key: &[u8],
+        expected: Option<&[u8]>,
+        new: Option<&[u8]>,
+    ) -> Result<bool> {
+        let mut writer = self.writer.lock().unwrap();
+        writer.check_writable()?;
+        if self.get_bytes(key)?.as_deref() != expected {
+            return Ok(false);
+        }
+        match (new, expected) {
+            (Some(value), _) => writer.set(&self.index, key, value, None)?,
+            (None, Some(_)) => writer.remove(&self.index, key)?,
+            (None, None) => {}
         }
+        Ok(true)
+    }
 
-        Ok(())
+    pub fn compare_and_set_bytes(&self, key: &[u8], expected: &[u8], new: &[u8]) -> Result<bool> {
+        KvsEngine::compare_and_set_bytes(self, key, expected, new)
+    }
+
+    pub fn set_if_absent_bytes(&self, key: &[u8], value: &[u8]) -> Result<bool> {
+        KvsEngine::set_if_absent_bytes(self, key, value)
+    }
+
+    pub fn remove_if_equals_bytes(&self, key: &[u8], expected: &[u8]) -> Result<bool> {
+        KvsEngine::remove_if_equals_bytes(self, key, expected)
     }
 
     /**
-     * ! impl {kv remove key}\n
-     * ! 1. read the log and build index\n
-     * ! 2. if the log with the key presents, serialize the
-     */
-    pub fn remove(&mut self, key: String) -> Result<()> {
-        if let Some(_pos) = self.index.get(&key) {
-            let cmd = Command::Remove { key: key.clone() };
-            serde_json::to_writer(&mut self.writer, &cmd)?;
-            self.writer.flush()?;
-            self.index.remove(&key).expect("key not found");
-            Ok(())
-        } else {
-            Err(KvError::KeyNotFound)
-        }
+     * ! atomically add `delta` to an integer value and return the new value,
+     * ! see `KvsEngine::incr_bytes`
+     */
+    pub fn incr_bytes(&self, key: &[u8], delta: i64) -> Result<i64> {
+        KvsEngine::incr_bytes(self, key, delta)
+    }
+
+    pub fn decr_bytes(&self, key: &[u8], delta: i64) -> Result<i64> {
+        KvsEngine::decr_bytes(self, key, delta)
+    }
+
+    /**
+     * ! text wrappers over the byte methods, see `KvsEngine`
+     */
+    pub fn get(&self, key: String) -> Result<Option<String>> {
+        KvsEngine::get(self, key)
+    }
+
+    pub fn set(&self, key: String, value: String) -> Result<()> {
+        KvsEngine::set(self, key, value)
+    }
+
+    pub fn remove(&self, key: String) -> Result<()> {
+        KvsEngine::remove(self, key)
+    }
+
+    pub fn set_with_ttl(&self, key: String, value: String, ttl: Duration) -> Result<()> {
+        KvsEngine::set_with_ttl(self, key, value, ttl)
+    }
+
+    pub fn compare_and_set(&self, key: String, expected: String, new: String) -> Result<bool> {
+        KvsEngine::compare_and_set(self, key, expected, new)
+    }
+
+    pub fn set_if_absent(&self, key: String, value: String) -> Result<bool> {
+        KvsEngine::set_if_absent(self, key, value)
+    }
+
+    pub fn remove_if_equals(&self, key: String, expected: String) -> Result<bool> {
+        KvsEngine::remove_if_equals(self, key, expected)
+    }
+
+    pub fn incr(&self, key: String, delta: i64) -> Result<i64> {
+        KvsEngine::incr(self, key, delta)
+    }
+
+    pub fn decr(&self, key: String, delta: i64) -> Result<i64> {
+        KvsEngine::decr(self, key, delta)
+    }
+
+    pub fn scan<R: RangeBounds<String>>(&self, range: R) -> StrScan<KvStore> {
+        KvsEngine::scan(self, range)
+    }
 
-        // Ok(())
+    pub fn scan_prefix(&self, prefix: &str) -> StrScan<KvStore> {
+        KvsEngine::scan_prefix(self, prefix)
     }
 
     pub fn open(path: impl Into<PathBuf>) -> Result<KvStore> {
+        KvStore::open_with_options(path, Options::default())
+    }
+
+    /**
+     * ! open the store in the directory at `path`, creating it if needed
+     * * `KvError::Locked` if another store has the directory open, see `DirLock`
+     */
+    pub fn open_with_options(path: impl Into<PathBuf>, options: Options) -> Result<KvStore> {
+        options.validate()?;
+        let path = path.into();
+        KvStore::open_in(path.clone(), options, false).map_err(|e| e.at(&path))
+    }
+
+    /**
+     * ! open the store at `path` only to read from it
+     * * nothing in the directory is created or changed, not even a torn record is cut off,
+     * * it never compacts and every write fails with `KvError::ReadOnly`
+     * * read-only stores share the directory lock, so any number of them can be open
+     * * at once, but not while a store has it open for writing
+     */
+    pub fn open_read_only(path: impl Into<PathBuf>) -> Result<KvStore> {
         let path = path.into();
+        KvStore::open_in(path.clone(), Options::default(), true).map_err(|e| e.at(&path))
+    }
+
+    fn open_in(path: PathBuf, options: Options, read_only: bool) -> Result<KvStore> {
+        let path = Arc::new(path);
 
         // let path = path.join(Path::new("/store_logs"));
 
-        if std::fs::metadata(&path).is_err() {
-            std::fs::create_dir_all(&path)?;
+        if !read_only && std::fs::metadata(&*path).is_err() {
+            std::fs::create_dir_all(&*path)?;
         }
+        // ! taken before anything in the directory is read or changed
+        let lock = if read_only {
+            DirLock::shared(&path)?
+        } else {
+            DirLock::exclusive(&path)?
+        };
 
-        let mut readers = HashMap::new();
-        let mut index = BTreeMap::new();
+        let list = KeyspaceList::read(&path)?;
+        let default = Arc::new(Index::new(DEFAULT_KEYSPACE_ID, DEFAULT_KEYSPACE));
+        let named: BTreeMap<String, Arc<Index>> = list
+            .keyspaces
+            .iter()
+            .map(|(id, name)| (name.clone(), Arc::new(Index::new(*id, name))))
+            .collect();
+        // ! records name their keyspace by id, those of a dropped keyspace find none
+        let indexes: HashMap<u32, Arc<Index>> = named
+            .values()
+            .chain(iter::once(&default))
+            .map(|index| (index.id, Arc::clone(index)))
+            .collect();
 
-        let gens = read_gens(&path)?;
+        let manifest = Manifest::read(&path)?;
+        let gens = if read_only {
+            live_gens(&path, manifest.as_ref())?
+        } else {
+            recover_dir(&path, manifest.as_ref())?;
+            read_gens(&path)?
+        };
+        let mut legacy = false;
+        let mut total = 0;
+        // ! the newest gen if writes can go on in it
+        let mut resume = None;
 
         for &gen in &gens {
             let log_p = log_path(&path, gen);
-            let mut reader = BufReaderWithPos::new(BufReader::new(File::open(&log_p)?))?;
-            load(gen, &mut reader, &mut index)?;
-            readers.insert(gen, reader);
+            // ! a compacted gen comes with a hint file, no need to replay its log then
+            let log_len = std::fs::metadata(&log_p)
+                .map_err(|e| KvError::Io(e).at(&log_p))?
+                .len();
+            if let Some(hints) = read_hint(&path, gen, log_len)? {
+                for Hint {
+                    keyspace,
+                    key,
+                    pos,
+                    len,
+                    expires,
+                } in hints
+                {
+                    let Some(index) = indexes.get(&keyspace) else {
+                        continue;
+                    };
+                    index.insert(
+                        key,
+                        CommandPos {
+                            gen,
+                            pos,
+                            len,
+                            expires,
+                        },
+                    );
+                }
+                total += log_len - FILE_HEADER_LEN;
+                continue;
+            }
+            // ! only the newest gen was being appended to, a torn record anywhere else is corruption
+            let newest = Some(&gen) == gens.last();
+            let (format, torn_at) = File::open(&log_p)
+                .map_err(KvError::Io)
+                .and_then(|file| BufReaderWithPos::new(BufReader::new(file)))
+                .and_then(|mut reader| load(gen, &mut reader, &indexes, newest))
+                .map_err(|e| e.at(&log_p))?;
+            legacy |= format.is_legacy();
+            total += torn_at.unwrap_or(log_len) - format.data_start(log_len);
+            if newest && !format.is_legacy() && torn_at.unwrap_or(log_len) < options.max_file_size {
+                resume = Some(gen);
+            }
+            if let Some(offset) = torn_at.filter(|_| !read_only) {
+                warn!(
+                    "Truncating torn record at offset {} of generation {}",
+                    offset, gen
+                );
+                OpenOptions::new()
+                    .write(true)
+                    .open(&log_p)
+                    .and_then(|file| file.set_len(offset))
+                    .map_err(|e| KvError::Io(e).at(&log_p))?;
+            }
         }
 
-        let curr_gen = gens.last().unwrap_or(&0) + 1;
+        let curr_gen = resume.unwrap_or(gens.last().unwrap_or(&0) + 1);
+        // ! whatever no index points to was overwritten, is a remove record or was dropped
+        let live: u64 = indexes.values().map(|index| index.bytes()).sum();
+        let keyspaces = Arc::new(Keyspaces {
+            default: Arc::clone(&default),
+            named: Mutex::new(named),
+        });
+
+        let writer = if read_only {
+            None
+        } else {
+            Some(new_log_file(&path, curr_gen)?)
+        };
+        let points = Arc::new(ReadPoints::default());
 
-        let writer = new_log_file(&path, curr_gen, &mut readers)?;
+        let reader = KvStoreReader {
+            path: Arc::clone(&path),
+            files: Arc::new(SkipMap::new()),
+        };
 
-        Ok(KvStore {
+        let writer = KvStoreWriter {
+            reader: reader.clone(),
             writer,
-            readers,
-            index,
+            keyspaces: Arc::clone(&keyspaces),
+            next_keyspace: list.next_id,
+            points: Arc::clone(&points),
             curr_gen,
-            compaction: 0,
             path,
-        })
-    }
+            durability: options.durability,
+            policy: options.compaction,
+            max_file_size: options.max_file_size,
+            total,
+            stale: total - live,
+            gens: gens.len() + usize::from(resume.is_none()),
+            unsynced: 0,
+            last_sync: Instant::now(),
+            compaction_thread: None,
+            read_only,
+            _lock: lock,
+        };
 
-    /**
-     * ! implement compaction
-     * * if multiple set is applied on same key, we only keep the latest set
-     * * we traverse index map since it contains key and its latest values
-     * * simply write all the value in the index to a new log file
-     * * then remove all the log files that has gen less than the latest one with compaction content
-     * ! remember to update writer in KvStore to avoid position mismatch
-     */
-    pub fn compaction(&mut self) -> Result<()> {
-        let compaction_gen = self.curr_gen + 1;
-        self.curr_gen += 2;
-        let mut compact_writer = new_log_file(&self.path, compaction_gen, &mut self.readers)?;
-        let mut curr_pos = 0;
-        self.writer = new_log_file(&self.path, self.curr_gen, &mut self.readers)?;
-
-        println!("index before 27: {:?}", self.index.get("key27"));
-        for cmd_pos in &mut self.index.values_mut() {
-            let reader = self
-                .readers
-                .get_mut(&cmd_pos.gen)
-                .expect("cannot find the reader");
-            if reader.pos != cmd_pos.pos {
-                reader.seek(SeekFrom::Start(cmd_pos.pos))?;
-            }
-            let mut reader = reader.take(cmd_pos.len);
-            let len = io::copy(&mut reader, &mut compact_writer)?;
-            *cmd_pos = CommandPos {
-                gen: compaction_gen,
-                pos: curr_pos,
-                len,
-            };
+        let store = KvStore {
+            index: default,
+            keyspaces,
+            reader,
+            writer: Arc::new(Mutex::new(writer)),
+            points,
+        };
 
-            curr_pos += len;
+        if read_only {
+            return Ok(store);
+        }
+        if let Durability::Periodic { interval, .. } = options.durability {
+            spawn_syncer(Arc::downgrade(&store.writer), interval);
         }
 
-        compact_writer.flush()?;
-
-        let stale_gens: Vec<_> = self
-            .readers
-            .keys()
-            .filter(|&gen| gen < &compaction_gen)
-            .cloned()
-            .collect();
-
-        for stale_gen in stale_gens {
-            self.readers.remove(&stale_gen).expect("the gen is removed");
-            std::fs::remove_file(Path::join(&self.path, &format!("{}.log", stale_gen)))?;
+        // ! upgrade path: compaction rewrites every live record in the current format
+        // ! and deletes the JSON logs it came from
+        if legacy {
+            info!(
+                "Upgrading logs in {} to the current format",
+                store.reader.path.display()
+            );
+            store.compaction()?;
+        } else {
+            store.writer.lock().unwrap().maybe_compact()?;
         }
 
-        println!("index for 27: {:?}", self.index.get("key27"));
+        Ok(store)
+    }
 
-        self.compaction = 0;
+    /**
+     * ! a read-only view of the store as it is now, see `Snapshot`
+     * * taken under the writer lock, so no write is half applied in it
+     */
+    pub fn snapshot(&self) -> Snapshot {
+        let _writer = self.writer.lock().unwrap();
+        Snapshot::new(self.clone(), self.points.register(self.index.id))
+    }
 
-        Ok(())
+    /**
+     * ! begin a transaction reading the store as it is now, see `Transaction`
+     */
+    pub fn begin(&self) -> Transaction {
+        Transaction::new(self.snapshot())
     }
-}
 
-fn new_log_file(
-    path: &Path,
-    gen: u64,
-    readers: &mut HashMap<u64, BufReaderWithPos>,
-) -> Result<BufWriterWithPos> {
-    let path = log_path(&path, gen);
-    let writer = BufWriterWithPos::new(BufWriter::new(
-        OpenOptions::new()
-            .create(true)
-            .write(true)
-            .append(true)
-            .open(&path)?,
-    ));
-    readers.insert(
-        gen,
-        BufReaderWithPos::new(BufReader::new(File::open(&path)?))?,
-    );
-    writer
+    /**
+     * ! the value `key` had at `point`
+     */
+    pub(crate) fn get_at(&self, point: &ReadPoint, key: &[u8]) -> Result<Option<Vec<u8>>> {
+        self.index.check()?;
+        loop {
+            let pos = self.index.get(key);
+            if let Some(entry) = point.before.get(key) {
+                // ! a saved position lives in a gen compaction keeps for the read point
+                return match *entry.value() {
+                    Some(pos) if pos.is_live() => self.reader.read_value(pos).map(Some),
+                    _ => Ok(None),
+                };
+            }
+            let pos = pos.filter(CommandPos::is_live);
+            match pos.map(|pos| self.reader.read_value(pos)) {
+                // ! moved by a compaction since the lookup, look again
+                Some(Err(KvError::Io(ref e))) if e.kind() == io::ErrorKind::NotFound => {}
+                res => return res.transpose(),
+            }
+        }
+    }
+
+    /**
+     * ! the first key of `range` at `point` from the front or the back with its value
+     * * the keys at `point` are the keys of the index that were not changed since, and the
+     * * keys whose old position was saved, the smaller of the two candidates is the next one
+     */
+    pub(crate) fn seek_at(
+        &self,
+        point: &ReadPoint,
+        (start, end): (Bound<&[u8]>, Bound<&[u8]>),
+        reverse: bool,
+    ) -> Result<Option<(Vec<u8>, Vec<u8>)>> {
+        let mut skipped: Option<Vec<u8>> = None;
+        loop {
+            let past = skipped.as_deref().map(Bound::Excluded);
+            let range = if reverse {
+                (start, past.unwrap_or(end))
+            } else {
+                (past.unwrap_or(start), end)
+            };
+            let in_index = first(self.index.range(range).map(|(key, _)| key), reverse);
+            let saved = point.before.range::<[u8], _>(range);
+            let in_point = first(saved.map(|entry| entry.key().clone()), reverse);
+            let key = match (in_index, in_point) {
+                (Some(a), Some(b)) if reverse => a.max(b),
+                (Some(a), Some(b)) => a.min(b),
+                (Some(key), None) | (None, Some(key)) => key,
+                (None, None) => return Ok(None),
+            };
+            match self.get_at(point, &key)? {
+                Some(value) => return Ok(Some((key, value))),
+                None => skipped = Some(key),
+            }
+        }
+    }
+
+    /**
+     * ! apply `batch` unless one of `keys` changed since `point`
+     */
+    pub(crate) fn commit<'a>(
+        &self,
+        point: &ReadPoint,
+        mut keys: impl Iterator<Item = &'a [u8]>,
+        batch: WriteBatch,
+    ) -> Result<()> {
+        let mut writer = self.writer.lock().unwrap();
+        if let Some(key) = keys.find(|key| point.changed(key)) {
+            return Err(KvError::Conflict(String::from_utf8_lossy(key).into_owned()));
+        }
+        writer.write(&self.index, batch)
+    }
+
+    /**
+     * ! force a compaction and wait for it to finish
+     * * waits for a background compaction in flight first, writes are blocked meanwhile
+     */
+    pub fn compaction(&self) -> Result<()> {
+        let mut writer = self.writer.lock().unwrap();
+        writer.check_writable()?;
+        if let Err(e) = writer.finish_compaction() {
+            error!("Background compaction failed: {}", e);
+        }
+        writer.start_compaction()?;
+        writer.finish_compaction()
+    }
+
+    /**
+     * ! a handle to the keyspace `name` of this store, `KvError::KeyspaceNotFound` if it
+     * ! was not created
+     * * the keyspace `"default"` always exists, it is the one `KvStore::open` returns
+     */
+    pub fn keyspace(&self, name: &str) -> Result<KvStore> {
+        match self.keyspaces.get(name) {
+            Some(index) => Ok(KvStore {
+                index,
+                ..self.clone()
+            }),
+            None => Err(KvError::KeyspaceNotFound(name.to_owned())),
+        }
+    }
+
+    /**
+     * ! create an empty keyspace and return a handle to it
+     * * its keys live next to those of the other keyspaces in the same logs, but have an
+     * * index of their own, so equal keys in two keyspaces never meet
+     */
+    pub fn create_keyspace(&self, name: &str) -> Result<KvStore> {
+        let index = self.writer.lock().unwrap().create_keyspace(name)?;
+        Ok(KvStore {
+            index,
+            ..self.clone()
+        })
+    }
+
+    /**
+     * ! drop a keyspace with all its keys
+     * * no record is written per key, the keyspace is removed from the KEYSPACES file and
+     * * its records are left for compaction to skip, handles to it fail with
+     * * `KvError::KeyspaceNotFound` from then on
+     */
+    pub fn drop_keyspace(&self, name: &str) -> Result<()> {
+        self.writer.lock().unwrap().drop_keyspace(name)
+    }
+
+    /**
+     * ! the names of the keyspaces created in this store, in order, without `"default"`
+     */
+    pub fn keyspaces(&self) -> Vec<String> {
+        self.keyspaces
+            .named
+            .lock()
+            .unwrap()
+            .keys()
+            .cloned()
+            .collect()
+    }
+}
+
+impl KvStoreWriter {
+    fn check_writable(&self) -> Result<()> {
+        if self.read_only {
+            return Err(KvError::ReadOnly);
+        }
+        Ok(())
+    }
+
+    fn set(&mut self, index: &Index, key: &[u8], value: &[u8], expires: Option<u64>) -> Result<()> {
+        self.check_writable()?;
+        index.check()?;
+        self.append(
+            index,
+            Command::Set {
+                keyspace: index.id,
+                key: key.to_vec(),
+                value: value.to_vec(),
+                expires,
+            },
+        )
+    }
+
+    fn remove(&mut self, index: &Index, key: &[u8]) -> Result<()> {
+        self.check_writable()?;
+        index.check()?;
+        if index.contains_key(key) {
+            self.append(
+                index,
+                Command::Remove {
+                    keyspace: index.id,
+                    key: key.to_vec(),
+                },
+            )
+        } else {
+            Err(KvError::key_not_found(key))
+        }
+    }
+
+    fn write(&mut self, index: &Index, batch: WriteBatch) -> Result<()> {
+        self.check_writable()?;
+        index.check()?;
+        batch.check(|key| index.contains_key(key))?;
+        if batch.is_empty() {
+            return Ok(());
+        }
+        let cmd = Command::Batch(batch.into_commands()).with_keyspace(index.id);
+        self.append(index, cmd)
+    }
+
+    /**
+     * ! write `cmd` to the active gen, then point `index` at it
+     * ! readers only see the new positions once the record is flushed
+     * * a roll-over or compaction that fails afterwards is logged, the record is
+     * * already written and indexed and the write stands
+     */
+    fn append(&mut self, index: &Index, cmd: Command) -> Result<()> {
+        let writer = self
+            .writer
+            .as_mut()
+            .expect("read-only stores do not append");
+        let pos = writer.pos;
+
+        let len = cmd.write_to(writer)?;
+        writer.flush()?;
+        self.after_write(len)?;
+
+        self.total += len;
+        self.index_command(index, cmd, pos, len);
+
+        if let Err(e) = self.maybe_roll_over() {
+            error!("Failed to roll over generation {}: {}", self.curr_gen, e);
+        }
+        if let Err(e) = self.maybe_compact() {
+            error!("Failed to start compaction: {}", e);
+        }
+        Ok(())
+    }
+
+    /**
+     * ! move writes on to a fresh gen once the active one reached `max_file_size`
+     * * writes stay on the active gen if the new one cannot be created
+     */
+    fn maybe_roll_over(&mut self) -> Result<()> {
+        if self
+            .writer
+            .as_ref()
+            .is_none_or(|writer| writer.pos < self.max_file_size)
+        {
+            return Ok(());
+        }
+        self.sync()?;
+        let writer = new_log_file(&self.path, self.curr_gen + 1)?;
+        self.curr_gen += 1;
+        self.writer = Some(writer);
+        self.gens += 1;
+        Ok(())
+    }
+
+    /**
+     * ! the KEYSPACES file as it is with `named`
+     */
+    fn keyspace_list(&self, named: &BTreeMap<String, Arc<Index>>) -> KeyspaceList {
+        KeyspaceList {
+            next_id: self.next_keyspace,
+            keyspaces: named
+                .values()
+                .map(|index| (index.id, index.name.clone()))
+                .collect(),
+        }
+    }
+
+    /**
+     * ! the KEYSPACES file naming the new keyspace is written before any record of it
+     */
+    fn create_keyspace(&mut self, name: &str) -> Result<Arc<Index>> {
+        self.check_writable()?;
+        check_name(name)?;
+        let keyspaces = Arc::clone(&self.keyspaces);
+        let mut named = keyspaces.named.lock().unwrap();
+        if named.contains_key(name) {
+            return Err(KvError::KeyspaceExists(name.to_owned()));
+        }
+        let index = Arc::new(Index::new(self.next_keyspace, name));
+        self.next_keyspace += 1;
+        named.insert(name.to_owned(), Arc::clone(&index));
+        if let Err(e) = self.keyspace_list(&named).write(&self.path) {
+            named.remove(name);
+            return Err(e);
+        }
+        Ok(index)
+    }
+
+    /**
+     * ! once the KEYSPACES file no longer names the keyspace, all its records are stale
+     */
+    fn drop_keyspace(&mut self, name: &str) -> Result<()> {
+        self.check_writable()?;
+        if name == DEFAULT_KEYSPACE {
+            return Err(KvError::InvalidKeyspace(name.to_owned()));
+        }
+        let keyspaces = Arc::clone(&self.keyspaces);
+        let mut named = keyspaces.named.lock().unwrap();
+        let index = named
+            .remove(name)
+            .ok_or_else(|| KvError::KeyspaceNotFound(name.to_owned()))?;
+        if let Err(e) = self.keyspace_list(&named).write(&self.path) {
+            named.insert(name.to_owned(), index);
+            return Err(e);
+        }
+        drop(named);
+        index.dropped.store(true, Ordering::SeqCst);
+        self.stale += index.bytes();
+        if let Err(e) = self.maybe_compact() {
+            error!("Failed to start compaction: {}", e);
+        }
+        Ok(())
+    }
+
+    /**
+     * ! insert the (key, CommandPos) pairs of a command written at `pos` into the index,
+     * ! counting the bytes it made stale
+     * * the record a set replaces is stale from now on, a removed record and the remove
+     * * record itself are both stale, and so is the frame around the records of a batch
+     */
+    fn index_command(&mut self, index: &Index, cmd: Command, pos: u64, len: u64) {
+        match cmd {
+            Command::Set { key, expires, .. } => {
+                self.points.save(index, &key);
+                if let Some(old) = index.get(&key) {
+                    self.stale += old.len;
+                }
+                let gen = self.curr_gen;
+                index.insert(
+                    key,
+                    CommandPos {
+                        gen,
+                        pos,
+                        len,
+                        expires,
+                    },
+                );
+            }
+            Command::Remove { key, .. } => {
+                self.points.save(index, &key);
+                let old = index.remove(&key).expect("key not found");
+                self.stale += old.len + len;
+            }
+            Command::Batch(cmds) => {
+                self.stale += RECORD_OVERHEAD;
+                let mut pos = pos + RECORD_OVERHEAD;
+                for cmd in cmds {
+                    let len = cmd.encoded_len();
+                    self.index_command(index, cmd, pos, len);
+                    pos += len;
+                }
+            }
+        }
+    }
+
+    /**
+     * ! apply the durability policy to a record of `len` bytes that was just flushed
+     */
+    fn after_write(&mut self, len: u64) -> Result<()> {
+        if self.durability == Durability::Never {
+            return Ok(());
+        }
+        self.unsynced += len;
+        match self.durability {
+            Durability::Always => self.sync(),
+            Durability::Periodic { interval, bytes }
+                if self.unsynced >= bytes || self.last_sync.elapsed() >= interval =>
+            {
+                self.sync()
+            }
+            _ => Ok(()),
+        }
+    }
+
+    /**
+     * ! fsync the active gen if anything was written since the last time
+     */
+    fn sync(&mut self) -> Result<()> {
+        if self.unsynced > 0 {
+            if let Some(writer) = self.writer.as_mut() {
+                writer.sync_data()?;
+            }
+            self.unsynced = 0;
+        }
+        self.last_sync = Instant::now();
+        Ok(())
+    }
+
+    /**
+     * ! start a background compaction if the policy asks for one and none is running
+     */
+    fn maybe_compact(&mut self) -> Result<()> {
+        if self.compacting() {
+            return Ok(());
+        }
+        if let Err(e) = self.finish_compaction() {
+            error!("Background compaction failed: {}", e);
+        }
+        if !self
+            .policy
+            .should_compact(self.stale, self.total, self.gens)
+        {
+            return Ok(());
+        }
+        self.start_compaction()
+    }
+
+    fn compacting(&self) -> bool {
+        self.compaction_thread
+            .as_ref()
+            .is_some_and(|running| !running.thread.is_finished())
+    }
+
+    /**
+     * ! switch writes to a fresh gen and compact everything before it on a background thread
+     * * the compacted log gets the gen right after the old active one, so replaying the
+     * * gens in order still ends with the latest write of every key
+     * * the index is copied while the Mutex is held, writes then continue to the new
+     * * active gen while the older gens are rewritten
+     * * expired keys are dropped from the index here and not copied, nothing else adds or
+     * * removes keys while the writer holds the Mutex
+     * * nothing changes if the new active gen cannot be created
+     */
+    fn start_compaction(&mut self) -> Result<()> {
+        let compaction_gen = self.curr_gen + 1;
+        self.sync()?;
+        let writer = new_log_file(&self.path, self.curr_gen + 2)?;
+        self.curr_gen += 2;
+        self.writer = Some(writer);
+
+        let mut entries = Vec::new();
+        for index in self.keyspaces.all() {
+            let (live, expired): (Vec<_>, Vec<_>) =
+                index.range(..).partition(|(_, pos)| pos.is_live());
+            for (key, pos) in expired {
+                index.remove(&key);
+                self.stale += pos.len;
+            }
+            entries.extend(
+                live.into_iter()
+                    .map(|(key, pos)| (Arc::clone(&index), key, pos)),
+            );
+        }
+        // ! the counts the compacted gen replaces, the new active gen is not one of them
+        let (total, stale, gens) = (self.total, self.stale, self.gens);
+        self.gens += 1;
+
+        let compaction = Compaction {
+            reader: self.reader.clone(),
+            points: Arc::clone(&self.points),
+            path: Arc::clone(&self.path),
+            gen: compaction_gen,
+            active_gen: self.curr_gen,
+            entries,
+        };
+        self.compaction_thread = Some(CompactionThread {
+            thread: thread::spawn(move || compaction.run()),
+            total,
+            stale,
+            gens,
+        });
+        Ok(())
+    }
+
+    /**
+     * ! wait for the last background compaction, returning how it went
+     * * only a compaction that succeeded takes the gens it replaced off the counts,
+     * * after a failed one they are still all there and count as before
+     */
+    fn finish_compaction(&mut self) -> Result<()> {
+        let running = match self.compaction_thread.take() {
+            Some(running) => running,
+            None => return Ok(()),
+        };
+        let copied = running
+            .thread
+            .join()
+            .unwrap_or_else(|_| Err(io::Error::other("compaction thread panicked").into()))?;
+        // ! what was written since the start stays, none of the copied records are stale
+        self.total = self.total - running.total + copied;
+        self.stale -= running.stale;
+        self.gens = self.gens - running.gens + 1;
+        Ok(())
+    }
+}
+
+impl Compaction {
+    /**
+     * ! implement compaction
+     * * if multiple set is applied on same key, we only keep the latest set
+     * * we traverse the copied index since it contains every key and its latest values
+     * * simply write all the value in the index to a new log file
+     * * records are decoded and written again, so records of JSON logs come out in the binary format
+     * * the new log is written to a temp file and fsynced, with a hint file listing the new positions
+     * * the manifest naming the new gen is the commit point, a crash before it leaves only temp
+     * * files behind, a crash after it is rolled forward by the next open (see `recover_dir`)
+     * * the index only switches to the compacted positions once the new file is renamed into place
+     * * then remove all the log files that has gen less than the latest one with compaction content
+     *
+     * ! a key written or removed since the copy keeps its newer index entry
+     * ! readers never wait on compaction, one that still points into a stale gen keeps
+     * ! its open handle or retries through the index
+     * ! returns the bytes of the records copied
+     */
+    fn run(self) -> Result<u64> {
+        let compaction_path = log_path(&self.path, self.gen);
+        let mut compact_writer = create_log_file(&tmp_path(&compaction_path))?;
+        let start = compact_writer.pos;
+        let mut curr_pos = start;
+
+        let mut moved = Vec::with_capacity(self.entries.len());
+        for (index, key, cmd_pos) in &self.entries {
+            // ! dropped since the copy, its records are not replayed anymore
+            if index.dropped.load(Ordering::SeqCst) {
+                continue;
+            }
+            let len = self
+                .reader
+                .read_command(*cmd_pos)?
+                .write_to(&mut compact_writer)?;
+            moved.push((
+                index,
+                key,
+                *cmd_pos,
+                CommandPos {
+                    gen: self.gen,
+                    pos: curr_pos,
+                    len,
+                    ..*cmd_pos
+                },
+            ));
+
+            curr_pos += len;
+        }
+
+        compact_writer.sync_data()?;
+
+        let hints: Vec<Hint> = moved
+            .iter()
+            .map(|&(index, key, _, pos)| Hint {
+                keyspace: index.id,
+                key: key.clone(),
+                pos: pos.pos,
+                len: pos.len,
+                expires: pos.expires,
+            })
+            .collect();
+        write_hint(&self.path, self.gen, curr_pos, &hints)?;
+
+        Manifest::new(vec![self.gen, self.active_gen]).write(&self.path)?;
+        std::fs::rename(tmp_path(&compaction_path), &compaction_path)?;
+        sync_dir(&self.path)?;
+
+        for (index, key, old_pos, new_pos) in moved {
+            index.replace(key, old_pos, new_pos);
+        }
+
+        self.reader.close_stale_files(self.gen);
+
+        let points = self.points.points.lock().unwrap();
+        let kept: HashSet<u64> = points
+            .iter()
+            .filter_map(Weak::upgrade)
+            .flat_map(|point| {
+                point
+                    .before
+                    .iter()
+                    .filter_map(|entry| entry.value().map(|pos| pos.gen))
+                    .collect::<Vec<_>>()
+            })
+            .collect();
+
+        let stale_gens: Vec<_> = read_gens(&self.path)?
+            .into_iter()
+            .filter(|&gen| gen < self.gen && !kept.contains(&gen))
+            .collect();
+
+        for stale_gen in stale_gens {
+            std::fs::remove_file(log_path(&self.path, stale_gen))?;
+            let hint = hint_path(&self.path, stale_gen);
+            if hint.exists() {
+                std::fs::remove_file(hint)?;
+            }
+        }
+
+        Ok(curr_pos - start)
+    }
+}
+
+impl Drop for KvStoreWriter {
+    fn drop(&mut self) {
+        if let Err(e) = self.finish_compaction() {
+            error!("Background compaction failed: {}", e);
+        }
+        if self.durability != Durability::Never {
+            if let Err(e) = self.sync() {
+                error!(
+                    "Failed to sync generation {} on close: {}",
+                    self.curr_gen, e
+                );
+            }
+        }
+    }
+}
+
+/**
+ * ! background half of `Durability::Periodic`
+ * * wakes up every `interval` to fsync what the writer left unsynced,
+ * * and stops once every handle to the store is gone
+ */
+fn spawn_syncer(writer: Weak<Mutex<KvStoreWriter>>, interval: Duration) {
+    thread::spawn(move || loop {
+        thread::sleep(interval);
+        let writer = match writer.upgrade() {
+            Some(writer) => writer,
+            None => break,
+        };
+        let mut writer = writer.lock().unwrap();
+        if writer.last_sync.elapsed() >= interval {
+            if let Err(e) = writer.sync() {
+                error!("Failed to sync generation {}: {}", writer.curr_gen, e);
+            }
+        }
+    });
+}
+
+impl KvsEngine for KvStore {
+    fn set_bytes(&self, key: &[u8], value: &[u8]) -> Result<()> {
+        KvStore::set_bytes(self, key, value)
+    }
+
+    fn set_with_ttl_bytes(&self, key: &[u8], value: &[u8], ttl: Duration) -> Result<()> {
+        KvStore::set_with_ttl_bytes(self, key, value, ttl)
+    }
+
+    fn get_bytes(&self, key: &[u8]) -> Result<Option<Vec<u8>>> {
+        KvStore::get_bytes(self, key)
+    }
+
+    fn remove_bytes(&self, key: &[u8]) -> Result<()> {
+        KvStore::remove_bytes(self, key)
+    }
+
+    fn write(&self, batch: WriteBatch) -> Result<()> {
+        KvStore::write(self, batch)
+    }
+
+    fn seek_bytes(
+        &self,
+        range: (Bound<&[u8]>, Bound<&[u8]>),
+        reverse: bool,
+    ) -> Result<Option<(Vec<u8>, Vec<u8>)>> {
+        KvStore::seek(self, range, reverse)
+    }
+
+    fn compare_and_swap_bytes(
+        &self,
+        key: &[u8],
+        expected: Option<&[u8]>,
+        new: Option<&[u8]>,
+    ) -> Result<bool> {
+        KvStore::compare_and_swap_bytes(self, key, expected, new)
+    }
+}
+
+/**
+ * ! the first item of `iter`, or the last one if `reverse`
+ */
+fn first<I: DoubleEndedIterator>(mut iter: I, reverse: bool) -> Option<I::Item> {
+    if reverse {
+        iter.next_back()
+    } else {
+        iter.next()
+    }
+}
+
+/**
+ * ! open the log file of gen for appending, a fresh file starts with the binary file header
+ */
+fn new_log_file(path: &Path, gen: u64) -> Result<BufWriterWithPos> {
+    create_log_file(&log_path(path, gen))
+}
+
+fn create_log_file(path: &Path) -> Result<BufWriterWithPos> {
+    let create = || {
+        let mut writer = BufWriterWithPos::new(BufWriter::new(
+            OpenOptions::new().create(true).append(true).open(path)?,
+        ))?;
+        if writer.pos == 0 {
+            write_file_header(&mut writer)?;
+            writer.flush()?;
+        }
+        Ok(writer)
+    };
+    create().map_err(|e: KvError| e.at(path))
+}
+
+#[cfg(unix)]
+fn read_exact_at(file: &File, buf: &mut [u8], offset: u64) -> io::Result<()> {
+    std::os::unix::fs::FileExt::read_exact_at(file, buf, offset)
+}
+
+#[cfg(windows)]
+fn read_exact_at(file: &File, mut buf: &mut [u8], mut offset: u64) -> io::Result<()> {
+    use std::os::windows::fs::FileExt;
+    while !buf.is_empty() {
+        match file.seek_read(buf, offset) {
+            Ok(0) => return Err(io::ErrorKind::UnexpectedEof.into()),
+            Ok(n) => {
+                buf = &mut buf[n..];
+                offset += n as u64;
+            }
+            Err(ref e) if e.kind() == io::ErrorKind::Interrupted => {}
+            Err(e) => return Err(e),
+        }
+    }
+    Ok(())
 }
 
 fn log_path(path: &Path, gen: u64) -> PathBuf {
     path.join(format!("{}.log", gen))
 }
 
+/**
+ * ! the gen of a `<gen>.<ext>` file
+ */
+fn parse_gen(file: &Path, ext: &str) -> Option<u64> {
+    if file.extension() != Some(ext.as_ref()) {
+        return None;
+    }
+    file.file_stem()?.to_str()?.parse().ok()
+}
+
+/**
+ * ! bring the directory back to the state the manifest describes after a crash
+ * * a compacted log the manifest names but that is still a temp file is renamed into place
+ * * every other temp file belongs to a compaction that never committed and is dropped
+ * * logs of gens the manifest no longer counts as live are deleted with their hints,
+ * * as well as hints whose log is gone
+ */
+fn recover_dir(path: &Path, manifest: Option<&Manifest>) -> Result<()> {
+    let mut changed = false;
+    for entry in std::fs::read_dir(path)? {
+        let file = entry?.path();
+        if file.extension() != Some("tmp".as_ref()) {
+            continue;
+        }
+        let target = file.with_extension("");
+        match (parse_gen(&target, "log"), manifest) {
+            (Some(gen), Some(manifest)) if manifest.contains(gen) && !target.exists() => {
+                std::fs::rename(&file, &target)?;
+            }
+            _ => std::fs::remove_file(&file)?,
+        }
+        changed = true;
+    }
+
+    for entry in std::fs::read_dir(path)? {
+        let file = entry?.path();
+        let stale = match (parse_gen(&file, "log"), parse_gen(&file, "hint")) {
+            (Some(gen), _) => manifest.is_some_and(|m| !m.is_live(gen)),
+            (_, Some(gen)) => {
+                manifest.is_some_and(|m| !m.is_live(gen)) || !log_path(path, gen).exists()
+            }
+            _ => false,
+        };
+        if stale {
+            std::fs::remove_file(&file)?;
+            changed = true;
+        }
+    }
+
+    if changed {
+        sync_dir(path)?;
+    }
+    Ok(())
+}
+
+/**
+ * ! the gens a read-only store loads, without touching the directory
+ * * leftovers of a compaction `recover_dir` would delete are skipped, unless the
+ * * compacted log is not renamed into place yet, then the gens it was compacted from
+ * * are all still there and are loaded instead
+ * * a directory that does not exist yet holds an empty store
+ */
+fn live_gens(path: &Path, manifest: Option<&Manifest>) -> Result<Vec<u64>> {
+    match std::fs::metadata(path) {
+        Err(ref e) if e.kind() == io::ErrorKind::NotFound => return Ok(Vec::new()),
+        Err(e) => return Err(e.into()),
+        Ok(_) => {}
+    }
+    let mut gens = read_gens(path)?;
+    if let Some(manifest) = manifest {
+        if manifest.gens().iter().all(|&gen| gens.contains(&gen)) {
+            gens.retain(|&gen| manifest.is_live(gen));
+        }
+    }
+    Ok(gens)
+}
+
 fn read_gens(path: &Path) -> Result<Vec<u64>> {
     let mut gens: Vec<u64> = std::fs::read_dir(path)?
         .flat_map(|res| match res {
@@ -324,36 +1678,123 @@ fn read_gens(path: &Path) -> Result<Vec<u64>> {
 }
 
 /**
- * ! load the whole log file, deserialize and insert into index
+ * ! load the whole log file, decode and insert into the indexes of the keyspaces
+ * * returns the format the file was written in
+ * * a record failing its checksum fails the load with the gen and offset of that record
+ * * unless `recover_tail` is set and the record is the torn last one of the file,
+ * * then loading stops there and the offset to truncate the file at is returned too
  */
 fn load(
     gen: u64,
     reader: &mut BufReaderWithPos,
-    index: &mut BTreeMap<String, CommandPos>,
-) -> Result<()> {
-    let mut pos = reader.seek(SeekFrom::Start(0))?;
+    indexes: &HashMap<u32, Arc<Index>>,
+    recover_tail: bool,
+) -> Result<(LogFormat, Option<u64>)> {
+    let file_len = reader.seek(SeekFrom::End(0))?;
+    reader.seek(SeekFrom::Start(0))?;
+    let mut head = vec![0; file_len.min(FILE_HEADER_LEN) as usize];
+    reader.read_exact(&mut head)?;
+    // ! a binary log of another format version is not read
+    let format = LogFormat::detect(&head).ok_or(KvError::Corrupted {
+        gen,
+        offset: MAGIC.len() as u64,
+    })?;
 
-    let mut stream = Deserializer::from_reader(reader).into_iter::<Command>();
+    let mut pos = reader.seek(SeekFrom::Start(format.data_start(file_len)))?;
 
-    while let Some(cmd) = stream.next() {
-        let new_pos = stream.byte_offset() as u64;
-        match cmd? {
-            Command::Set { key, .. } => {
-                index.insert(
-                    key,
+    match format {
+        LogFormat::Binary => {
+            let mut records = RecordReader::new(reader, gen, pos, file_len);
+            loop {
+                match records.next_record() {
+                    Ok(Some((cmd, pos, len))) => apply(
+                        indexes,
+                        cmd,
+                        CommandPos {
+                            gen,
+                            pos,
+                            len,
+                            expires: None,
+                        },
+                    ),
+                    Ok(None) => break,
+                    Err(KvError::Corrupted { offset, .. }) if recover_tail && records.torn() => {
+                        return Ok((format, Some(offset)));
+                    }
+                    Err(e) => return Err(e),
+                }
+            }
+        }
+        LogFormat::Json => {
+            let mut stream = Deserializer::from_reader(reader).into_iter::<JsonCommand>();
+
+            while let Some(cmd) = stream.next() {
+                let new_pos = stream.byte_offset() as u64;
+                let cmd = match cmd {
+                    Ok(cmd) => cmd.into(),
+                    Err(ref e) if recover_tail && e.is_eof() => return Ok((format, Some(pos))),
+                    Err(e) if e.is_io() => {
+                        return Err(KvError::Log {
+                            gen,
+                            offset: pos,
+                            source: e.into(),
+                        })
+                    }
+                    Err(_) => return Err(KvError::Corrupted { gen, offset: pos }),
+                };
+                apply(
+                    indexes,
+                    cmd,
                     CommandPos {
                         gen,
                         pos,
                         len: new_pos - pos,
+                        expires: None,
                     },
                 );
+                pos = new_pos;
             }
-            Command::Remove { key, .. } => {
+        }
+    }
+
+    Ok((format, None))
+}
+
+/**
+ * ! replay one command found at `pos` onto the index of its keyspace, if it still exists
+ */
+fn apply(indexes: &HashMap<u32, Arc<Index>>, cmd: Command, pos: CommandPos) {
+    match cmd {
+        Command::Set {
+            keyspace,
+            key,
+            expires,
+            ..
+        } => {
+            if let Some(index) = indexes.get(&keyspace) {
+                index.insert(key, CommandPos { expires, ..pos });
+            }
+        }
+        Command::Remove { keyspace, key } => {
+            if let Some(index) = indexes.get(&keyspace) {
                 index.remove(&key).unwrap();
             }
         }
-        pos = new_pos;
+        Command::Batch(cmds) => {
+            let mut offset = pos.pos + RECORD_OVERHEAD;
+            for cmd in cmds {
+                let len = cmd.encoded_len();
+                apply(
+                    indexes,
+                    cmd,
+                    CommandPos {
+                        pos: offset,
+                        len,
+                        ..pos
+                    },
+                );
+                offset += len;
+            }
+        }
     }
-
-    Ok(())
 }
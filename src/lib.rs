@@ -1,5 +1,43 @@
 pub mod kvs;
 pub use kvs::KvStore;
 
+mod hint;
+mod keyspace;
+mod lock;
+mod manifest;
+mod record;
+
+pub mod options;
+pub use options::{CompactionPolicy, Durability, Options};
+
+pub mod config;
+pub use config::Config;
+
+pub mod batch;
+pub use batch::WriteBatch;
+
+pub mod transaction;
+pub use transaction::Transaction;
+
+pub mod engine;
+pub use engine::{EngineKind, KvsEngine};
+
+pub mod mem;
+pub use mem::MemStore;
+
+pub mod scan;
+pub use scan::{Scan, ScanSource, StrScan};
+
+pub mod snapshot;
+pub use snapshot::Snapshot;
+
+pub mod protocol;
+
+pub mod client;
+pub use client::KvsClient;
+
+pub mod server;
+pub use server::KvsServer;
+
 pub mod error;
 pub use error::Result;
@@ -0,0 +1,61 @@
+use crate::error::{KvError, Result};
+use std::fs::{File, OpenOptions};
+use std::io;
+use std::path::Path;
+
+/**
+ * ! the LOCK file keeps two processes from opening one data directory at the same time
+ * * a store opened for writing holds an exclusive lock on it, read-only stores share one,
+ * * so readers only ever wait out a writer, not each other
+ * * the lock belongs to the open file and goes away with it, also when the process dies
+ * * a read-only store does not create the file, a directory without it was never opened
+ * * for writing by this version and is read without a lock
+ */
+const LOCK: &str = "LOCK";
+
+pub(crate) struct DirLock {
+    _file: Option<File>,
+}
+
+impl DirLock {
+    /**
+     * ! `KvError::Locked` if any other store has the directory open
+     */
+    pub(crate) fn exclusive(path: &Path) -> Result<DirLock> {
+        let file = OpenOptions::new()
+            .read(true)
+            .write(true)
+            .create(true)
+            .truncate(false)
+            .open(path.join(LOCK))
+            .map_err(|e| KvError::Io(e).at(&path.join(LOCK)))?;
+        match file.try_lock() {
+            Ok(()) => Ok(DirLock { _file: Some(file) }),
+            Err(e) => Err(lock_error(path, e)),
+        }
+    }
+
+    /**
+     * ! `KvError::Locked` if a store has the directory open for writing
+     */
+    pub(crate) fn shared(path: &Path) -> Result<DirLock> {
+        let file = match File::open(path.join(LOCK)) {
+            Ok(file) => file,
+            Err(ref e) if e.kind() == io::ErrorKind::NotFound => {
+                return Ok(DirLock { _file: None })
+            }
+            Err(e) => return Err(KvError::Io(e).at(&path.join(LOCK))),
+        };
+        match file.try_lock_shared() {
+            Ok(()) => Ok(DirLock { _file: Some(file) }),
+            Err(e) => Err(lock_error(path, e)),
+        }
+    }
+}
+
+fn lock_error(path: &Path, e: std::fs::TryLockError) -> KvError {
+    match e {
+        std::fs::TryLockError::WouldBlock => KvError::Locked(path.display().to_string()),
+        std::fs::TryLockError::Error(e) => KvError::Io(e).at(&path.join(LOCK)),
+    }
+}
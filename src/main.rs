@@ -1,48 +1,345 @@
+use kv::config::{CONFIG_ENV, DATA_DIR_ENV};
+use kv::engine::{detect_engine, select_engine, EngineKind};
 use kv::error::KvError;
 use kv::kvs::KvStore;
+use kv::{Config, Durability, KvsEngine, MemStore, Options, Result};
 use std::env::current_dir;
+use std::ops::Bound;
+use std::path::PathBuf;
 use std::process::exit;
+use std::time::Duration;
 use structopt::StructOpt;
 
+/**
+ * ! exit code of a conditional `set` or `rm` whose condition did not hold,
+ * ! other failures exit with the code of their error, see `KvError::exit_code`
+ */
+const EXIT_CONDITION_FAILED: i32 = 2;
+
+/**
+ * ! the exit codes at the end of `kv --help`, one per `KvError::exit_code`
+ */
+const EXIT_CODES: &str = "EXIT CODES:
+     0  success
+     1  invalid arguments
+     2  the condition of a conditional set or rm did not hold
+     3  key not found
+     4  I/O error
+     5  invalid JSON
+     6  a log record could not be read
+     7  corrupted log record
+     8  invalid manifest
+     9  unexpected record in a log
+    10  invalid option or config file
+    11  unknown engine
+    12  data directory written by another engine
+    13  key or value is not UTF-8
+    14  transaction conflict
+    15  value is not an integer
+    16  counter would overflow
+    17  keyspace not found
+    18  keyspace already exists
+    19  invalid keyspace name
+    20  data directory in use by another process
+    21  store is open read-only
+    22  I/O error on a file of the store";
+
 fn main() {
-    let path = current_dir().expect("fail to get current directory");
-    let mut store = KvStore::open(&path).expect("fail to create and load the dir");
-    match KvCli::from_args() {
-        KvCli::Get { key } => {
-            match store.get(key) {
-                Ok(Some(value)) => println!("{}", value),
-                Ok(None) => println!("Key not found"),
-                Err(e) => {
-                    eprintln!("{:?}", e);
-                    exit(1)
-                }
+    env_logger::Builder::from_env(env_logger::Env::default().default_filter_or("warn")).init();
+    let opt = Opt::from_args();
+    let config = match &opt.config {
+        Some(path) => Config::load(path),
+        None => Ok(Config::default()),
+    };
+    let config = match config {
+        Ok(config) => config,
+        Err(e) => fail(e),
+    };
+    // ! flags and environment variables first, then the config file, then the defaults
+    let path = match opt.data_dir.or_else(|| config.data_dir.clone()) {
+        Some(path) => path,
+        None => current_dir().expect("fail to get current directory"),
+    };
+    // ! reading keys leaves the directory as it is
+    let read_only = matches!(opt.cmd, KvCli::Get { .. } | KvCli::Scan(_) | KvCli::Keys(_));
+    let requested = opt.engine.or(config.engine);
+    let engine = if read_only {
+        detect_engine(&path, requested)
+    } else {
+        select_engine(&path, requested)
+    };
+    let engine = match engine {
+        Ok(engine) => engine,
+        Err(e) => fail(e),
+    };
+    match engine {
+        EngineKind::Kvs => {
+            let options = Options {
+                durability: opt.durability.or(config.durability).unwrap_or_default(),
+                ..config.options()
+            };
+            let store = if read_only {
+                KvStore::open_read_only(&path)
+            } else {
+                KvStore::open_with_options(&path, options)
+            };
+            match store {
+                Ok(store) => exit(run(store, opt.cmd)),
+                Err(e) => fail(e),
             }
-            exit(0);
-        }
-        KvCli::Set { key, value } => {
-            store.set(key, value).expect("set fail");
-            exit(0);
         }
-        KvCli::Remove { key } => match store.remove(key) {
-            Ok(_) => exit(0),
-            Err(KvError::KeyNotFound) => {
+        EngineKind::Memory => exit(run(MemStore::new(), opt.cmd)),
+    }
+}
+
+/**
+ * ! carry out `cmd` and return the code to exit with
+ * * `store` is dropped on return, before the process exits, so that it can finish
+ * * a background compaction and sync what it wrote
+ */
+fn run<E: KvsEngine>(store: E, cmd: KvCli) -> i32 {
+    match cmd {
+        KvCli::Get { key } => match store.get(key) {
+            Ok(Some(value)) => {
+                println!("{}", value);
+                0
+            }
+            Ok(None) => {
                 println!("Key not found");
-                exit(1)
+                0
             }
-            _ => exit(1),
+            Err(e) => report(e),
         },
+        KvCli::Set {
+            key,
+            value,
+            if_absent: true,
+            ..
+        } => conditional(store.set_if_absent(key, value)),
+        KvCli::Set {
+            key,
+            value,
+            expect: Some(expected),
+            ..
+        } => conditional(store.compare_and_set(key, expected, value)),
+        KvCli::Set {
+            key,
+            value,
+            ttl: Some(ttl),
+            ..
+        } => done(store.set_with_ttl(key, value, Duration::from_secs(ttl))),
+        KvCli::Set { key, value, .. } => done(store.set(key, value)),
+        KvCli::Remove {
+            key,
+            expect: Some(expected),
+        } => conditional(store.remove_if_equals(key, expected)),
+        KvCli::Remove { key, .. } => done(store.remove(key)),
+        KvCli::Incr { key, delta } => print_counter(store.incr(key, delta)),
+        KvCli::Decr { key, delta } => print_counter(store.decr(key, delta)),
+        KvCli::Scan(args) => {
+            for pair in scan(&store, args) {
+                match pair {
+                    Ok((key, value)) => println!("{}\t{}", key, value),
+                    Err(e) => return report(e),
+                }
+            }
+            0
+        }
+        KvCli::Keys(args) => {
+            for pair in scan(&store, args) {
+                match pair {
+                    Ok((key, _)) => println!("{}", key),
+                    Err(e) => return report(e),
+                }
+            }
+            0
+        }
     }
 }
 
+/**
+ * ! report `e` and return its exit code
+ * * a missing key is reported on stdout, the way `get` does
+ */
+fn report(e: KvError) -> i32 {
+    match e {
+        KvError::KeyNotFound(_) => println!("Key not found"),
+        _ => eprintln!("{}", e),
+    }
+    e.exit_code()
+}
+
+/**
+ * ! report `e` and exit, for failures before a store is open
+ */
+fn fail(e: KvError) -> ! {
+    exit(report(e))
+}
+
+/**
+ * ! the exit code of a write
+ */
+fn done(res: Result<()>) -> i32 {
+    match res {
+        Ok(()) => 0,
+        Err(e) => report(e),
+    }
+}
+
+/**
+ * ! the exit code of a conditional write, `EXIT_CONDITION_FAILED` if its condition did not hold
+ */
+fn conditional(applied: Result<bool>) -> i32 {
+    match applied {
+        Ok(true) => 0,
+        Ok(false) => {
+            println!("Condition not met");
+            EXIT_CONDITION_FAILED
+        }
+        Err(e) => report(e),
+    }
+}
+
+/**
+ * ! print the new value of a counter and return the exit code
+ */
+fn print_counter(value: Result<i64>) -> i32 {
+    match value {
+        Ok(value) => {
+            println!("{}", value);
+            0
+        }
+        Err(e) => report(e),
+    }
+}
+
+/**
+ * ! the pairs `args` select, in the order and number they ask for
+ */
+fn scan<E: KvsEngine>(
+    store: &E,
+    args: ScanArgs,
+) -> Box<dyn Iterator<Item = Result<(String, String)>>> {
+    let scan = match args.prefix {
+        Some(prefix) => store.scan_prefix(&prefix),
+        None => store.scan((
+            args.start.map_or(Bound::Unbounded, Bound::Included),
+            args.end.map_or(Bound::Unbounded, Bound::Excluded),
+        )),
+    };
+    let limit = args.limit.unwrap_or(usize::MAX);
+    if args.reverse {
+        Box::new(scan.rev().take(limit))
+    } else {
+        Box::new(scan.take(limit))
+    }
+}
+
+#[derive(StructOpt, Debug)]
+#[structopt(
+    name = env!("CARGO_PKG_NAME"),
+    about = env!("CARGO_PKG_DESCRIPTION"),
+    after_help = EXIT_CODES
+)]
+struct Opt {
+    /// Directory of the store; defaults to the config file's data_dir, then the current directory
+    #[structopt(long, global = true, env = DATA_DIR_ENV, parse(from_os_str))]
+    data_dir: Option<PathBuf>,
+
+    /// TOML file with defaults for data_dir, engine, durability, max_file_size and [compaction]
+    #[structopt(long, global = true, env = CONFIG_ENV, parse(from_os_str))]
+    config: Option<PathBuf>,
+
+    /// Storage engine, either "kvs" or "memory"; defaults to the one the directory was written with
+    #[structopt(long, global = true)]
+    engine: Option<EngineKind>,
+
+    /// When writes are fsynced: "always", "never", "periodic" or "periodic:<ms>:<bytes>"; defaults to "never"
+    #[structopt(long, global = true)]
+    durability: Option<Durability>,
+
+    #[structopt(subcommand)]
+    cmd: KvCli,
+}
+
 #[derive(StructOpt, Debug)]
-#[structopt(name = env!("CARGO_PKG_NAME"), about = env!("CARGO_PKG_DESCRIPTION"))]
 enum KvCli {
     #[structopt(name = "get")]
     Get { key: String },
 
+    /// Set a key; with --if-absent or --expect, exit with 2 if the condition does not hold
     #[structopt(name = "set")]
-    Set { key: String, value: String },
+    Set {
+        key: String,
+        value: String,
+
+        /// Only set the key if it does not exist
+        #[structopt(long, conflicts_with = "expect")]
+        if_absent: bool,
+
+        /// Only set the key if its current value is this one
+        #[structopt(long)]
+        expect: Option<String>,
 
+        /// Seconds after which the key expires and behaves as absent
+        #[structopt(long, conflicts_with_all = &["if-absent", "expect"])]
+        ttl: Option<u64>,
+    },
+
+    /// Remove a key; with --expect, exit with 2 if the condition does not hold
     #[structopt(name = "rm")]
-    Remove { key: String },
+    Remove {
+        key: String,
+
+        /// Only remove the key if its current value is this one
+        #[structopt(long)]
+        expect: Option<String>,
+    },
+
+    /// Add to the integer value of a key, a missing key counts as 0, and print the result
+    #[structopt(name = "incr")]
+    Incr {
+        key: String,
+        #[structopt(default_value = "1")]
+        delta: i64,
+    },
+
+    /// Subtract from the integer value of a key and print the result
+    #[structopt(name = "decr")]
+    Decr {
+        key: String,
+        #[structopt(default_value = "1")]
+        delta: i64,
+    },
+
+    /// Print the pairs of a range in key order, key and value separated by a tab
+    #[structopt(name = "scan")]
+    Scan(ScanArgs),
+
+    /// Print the keys of a range in order
+    #[structopt(name = "keys")]
+    Keys(ScanArgs),
+}
+
+#[derive(StructOpt, Debug)]
+struct ScanArgs {
+    /// First key of the range
+    #[structopt(long, conflicts_with = "prefix")]
+    start: Option<String>,
+
+    /// Key the range stops before
+    #[structopt(long, conflicts_with = "prefix")]
+    end: Option<String>,
+
+    /// Only keys starting with this prefix
+    #[structopt(long)]
+    prefix: Option<String>,
+
+    /// Walk the range from its last key to its first
+    #[structopt(long)]
+    reverse: bool,
+
+    /// Print at most this many keys
+    #[structopt(long)]
+    limit: Option<usize>,
 }
@@ -0,0 +1,103 @@
+use crate::error::{KvError, Result};
+use std::fs::{self, File};
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+
+/**
+ * ! the manifest names the generations that were live after the last compaction
+ * * one gen per line, replaced atomically through a temp file and a rename
+ * * a gen is live if the manifest names it or it is newer than every gen named,
+ * * anything older that is not named was compacted away
+ * * a directory without manifest has never been compacted, all its gens are live
+ */
+pub(crate) const MANIFEST: &str = "MANIFEST";
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) struct Manifest {
+    gens: Vec<u64>,
+}
+
+impl Manifest {
+    pub(crate) fn new(mut gens: Vec<u64>) -> Manifest {
+        gens.sort_unstable();
+        gens.dedup();
+        Manifest { gens }
+    }
+
+    pub(crate) fn contains(&self, gen: u64) -> bool {
+        self.gens.binary_search(&gen).is_ok()
+    }
+
+    pub(crate) fn gens(&self) -> &[u64] {
+        &self.gens
+    }
+
+    pub(crate) fn is_live(&self, gen: u64) -> bool {
+        match self.gens.last() {
+            Some(&newest) => gen > newest || self.contains(gen),
+            None => true,
+        }
+    }
+
+    pub(crate) fn read(path: &Path) -> Result<Option<Manifest>> {
+        let file = path.join(MANIFEST);
+        let content = match fs::read_to_string(&file) {
+            Ok(content) => content,
+            Err(ref e) if e.kind() == io::ErrorKind::NotFound => return Ok(None),
+            Err(e) => return Err(KvError::Io(e).at(&file)),
+        };
+        let gens = content
+            .lines()
+            .filter(|line| !line.is_empty())
+            .map(|line| {
+                line.trim()
+                    .parse::<u64>()
+                    .map_err(|_| KvError::InvalidManifest(line.to_owned()))
+            })
+            .collect::<Result<Vec<u64>>>()?;
+        Ok(Some(Manifest::new(gens)))
+    }
+
+    /**
+     * ! atomically replace the manifest, the new one is durable when this returns
+     */
+    pub(crate) fn write(&self, path: &Path) -> Result<()> {
+        let content: String = self.gens.iter().map(|gen| format!("{}\n", gen)).collect();
+        write_atomic(&path.join(MANIFEST), content.as_bytes())
+    }
+}
+
+/**
+ * ! where a file is written before it is renamed into place
+ */
+pub(crate) fn tmp_path(path: &Path) -> PathBuf {
+    let mut name = path.file_name().unwrap_or_default().to_owned();
+    name.push(".tmp");
+    path.with_file_name(name)
+}
+
+/**
+ * ! write `content` to `path` through a fsynced temp file and a rename
+ */
+pub(crate) fn write_atomic(path: &Path, content: &[u8]) -> Result<()> {
+    let tmp = tmp_path(path);
+    let mut file = File::create(&tmp)?;
+    file.write_all(content)?;
+    file.sync_data()?;
+    fs::rename(&tmp, path)?;
+    sync_dir(path.parent().unwrap_or_else(|| Path::new(".")))
+}
+
+/**
+ * ! make renames and removals in `dir` durable
+ */
+#[cfg(unix)]
+pub(crate) fn sync_dir(dir: &Path) -> Result<()> {
+    File::open(dir)?.sync_all()?;
+    Ok(())
+}
+
+#[cfg(not(unix))]
+pub(crate) fn sync_dir(_dir: &Path) -> Result<()> {
+    Ok(())
+}
@@ -0,0 +1,136 @@
+use crate::batch::WriteBatch;
+use crate::engine::KvsEngine;
+use crate::error::{KvError, Result};
+use crate::record::Command;
+use crossbeam_skiplist::SkipMap;
+use std::ops::Bound;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+/**
+ * ! a storage engine that keeps everything in a SkipMap
+ * * nothing is written to disk, the content is gone once the last handle is dropped,
+ * * which makes it a cheap stand-in for `KvStore` in tests
+ * * writes take a lock so that a conditional write cannot interleave with another write
+ * * expired keys stay in the map until the key is written again, reads skip them
+ */
+#[derive(Clone, Default)]
+pub struct MemStore {
+    map: Arc<SkipMap<Vec<u8>, Entry>>,
+    write_lock: Arc<Mutex<()>>,
+}
+
+struct Entry {
+    value: Vec<u8>,
+    expires: Option<Instant>,
+}
+
+impl Entry {
+    fn is_live(&self) -> bool {
+        self.expires.is_none_or(|expires| expires > Instant::now())
+    }
+}
+
+impl MemStore {
+    pub fn new() -> MemStore {
+        MemStore::default()
+    }
+
+    fn insert(&self, key: Vec<u8>, value: Vec<u8>, expires: Option<Instant>) {
+        self.map.insert(key, Entry { value, expires });
+    }
+
+    /**
+     * ! the value of `key` unless it is absent or expired
+     */
+    fn live(&self, key: &[u8]) -> Option<Vec<u8>> {
+        self.map
+            .get(key)
+            .filter(|entry| entry.value().is_live())
+            .map(|entry| entry.value().value.clone())
+    }
+}
+
+impl KvsEngine for MemStore {
+    fn set_bytes(&self, key: &[u8], value: &[u8]) -> Result<()> {
+        let _lock = self.write_lock.lock().unwrap();
+        self.insert(key.to_vec(), value.to_vec(), None);
+        Ok(())
+    }
+
+    fn set_with_ttl_bytes(&self, key: &[u8], value: &[u8], ttl: Duration) -> Result<()> {
+        let _lock = self.write_lock.lock().unwrap();
+        self.insert(
+            key.to_vec(),
+            value.to_vec(),
+            Instant::now().checked_add(ttl),
+        );
+        Ok(())
+    }
+
+    fn get_bytes(&self, key: &[u8]) -> Result<Option<Vec<u8>>> {
+        Ok(self.live(key))
+    }
+
+    fn remove_bytes(&self, key: &[u8]) -> Result<()> {
+        let _lock = self.write_lock.lock().unwrap();
+        match self.map.remove(key) {
+            Some(entry) if entry.value().is_live() => Ok(()),
+            _ => Err(KvError::key_not_found(key)),
+        }
+    }
+
+    /**
+     * ! nothing to recover after a crash here, concurrent readers may see a batch half applied
+     */
+    fn write(&self, batch: WriteBatch) -> Result<()> {
+        let _lock = self.write_lock.lock().unwrap();
+        batch.check(|key| self.live(key).is_some())?;
+        for cmd in batch.into_commands() {
+            match cmd {
+                Command::Set { key, value, .. } => self.insert(key, value, None),
+                Command::Remove { key, .. } => {
+                    self.map.remove(&key);
+                }
+                Command::Batch(_) => unreachable!("batches do not nest"),
+            }
+        }
+        Ok(())
+    }
+
+    fn seek_bytes(
+        &self,
+        range: (Bound<&[u8]>, Bound<&[u8]>),
+        reverse: bool,
+    ) -> Result<Option<(Vec<u8>, Vec<u8>)>> {
+        let mut entries = self
+            .map
+            .range::<[u8], _>(range)
+            .filter(|entry| entry.value().is_live());
+        let entry = if reverse {
+            entries.next_back()
+        } else {
+            entries.next()
+        };
+        Ok(entry.map(|entry| (entry.key().clone(), entry.value().value.clone())))
+    }
+
+    fn compare_and_swap_bytes(
+        &self,
+        key: &[u8],
+        expected: Option<&[u8]>,
+        new: Option<&[u8]>,
+    ) -> Result<bool> {
+        let _lock = self.write_lock.lock().unwrap();
+        if self.live(key).as_deref() != expected {
+            return Ok(false);
+        }
+        match new {
+            Some(value) => self.insert(key.to_vec(), value.to_vec(), None),
+            None => {
+                self.map.remove(key);
+            }
+        }
+        Ok(true)
+    }
+}
@@ -0,0 +1,203 @@
+use crate::error::{KvError, Result};
+use std::str::FromStr;
+use std::time::Duration;
+
+/**
+ * ! tuning knobs of a KvStore, `KvStore::open` uses `Options::default()`
+ */
+#[derive(Debug, Clone)]
+pub struct Options {
+    pub durability: Durability,
+    pub compaction: CompactionPolicy,
+
+    /**
+     * ! writes move on to a new generation once the active one has this many bytes,
+     * ! a store opened again keeps appending to the last generation while it is smaller
+     */
+    pub max_file_size: u64,
+}
+
+impl Options {
+    pub const DEFAULT_MAX_FILE_SIZE: u64 = 64 * 1024 * 1024;
+
+    /**
+     * ! `KvError::InvalidOption` if the durability or the compaction policy is invalid
+     */
+    pub fn validate(&self) -> Result<()> {
+        self.durability.validate()?;
+        self.compaction.validate()
+    }
+}
+
+impl Default for Options {
+    fn default() -> Options {
+        Options {
+            durability: Durability::default(),
+            compaction: CompactionPolicy::default(),
+            max_file_size: Options::DEFAULT_MAX_FILE_SIZE,
+        }
+    }
+}
+
+/**
+ * ! when acknowledged writes are forced to disk with fsync
+ * * every mode flushes the write buffer to the OS after each write,
+ * * they only differ in how much can be lost on power failure
+ */
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Durability {
+    /**
+     * ! fsync before every set and remove returns
+     */
+    Always,
+
+    /**
+     * ! group commit: fsync once `interval` passed or `bytes` were written since the last one,
+     * ! whichever comes first, a background thread takes care of the interval when writes stop
+     * ! the interval must not be zero
+     */
+    Periodic { interval: Duration, bytes: u64 },
+
+    /**
+     * ! leave it to the OS
+     */
+    #[default]
+    Never,
+}
+
+impl Durability {
+    pub const DEFAULT_INTERVAL: Duration = Duration::from_millis(100);
+    pub const DEFAULT_BYTES: u64 = 1024 * 1024;
+
+    /**
+     * ! `KvError::InvalidOption` for a periodic interval of zero, which would keep the
+     * ! background thread syncing without pause
+     */
+    pub fn validate(&self) -> Result<()> {
+        match self {
+            Durability::Periodic { interval, .. } if interval.is_zero() => {
+                Err(KvError::InvalidOption("durability interval 0".to_owned()))
+            }
+            _ => Ok(()),
+        }
+    }
+}
+
+/**
+ * ! "always", "never", "periodic" (with the default interval and bytes),
+ * ! or "periodic:<ms>:<bytes>" with `ms` above 0
+ */
+impl FromStr for Durability {
+    type Err = KvError;
+
+    fn from_str(s: &str) -> Result<Durability> {
+        let invalid = || KvError::InvalidOption(format!("durability {}", s));
+        let mut parts = s.split(':');
+        match (parts.next(), parts.next(), parts.next(), parts.next()) {
+            (Some("always"), None, _, _) => Ok(Durability::Always),
+            (Some("never"), None, _, _) => Ok(Durability::Never),
+            (Some("periodic"), None, _, _) => Ok(Durability::Periodic {
+                interval: Durability::DEFAULT_INTERVAL,
+                bytes: Durability::DEFAULT_BYTES,
+            }),
+            (Some("periodic"), Some(ms), Some(bytes), None) => Ok(Durability::Periodic {
+                interval: Duration::from_millis(
+                    ms.parse()
+                        .ok()
+                        .filter(|&ms: &u64| ms > 0)
+                        .ok_or_else(invalid)?,
+                ),
+                bytes: bytes.parse().map_err(|_| invalid())?,
+            }),
+            _ => Err(invalid()),
+        }
+    }
+}
+
+/**
+ * ! when a store compacts its logs on its own
+ * * a record is stale once a later set or remove of its key replaced it, and every remove
+ * * record is stale itself, compaction drops all of them
+ * * compaction starts as soon as any of the thresholds that are set is reached,
+ * * with none set the store only compacts when `KvStore::compaction` is called
+ */
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct CompactionPolicy {
+    /**
+     * ! bytes of stale records across all generations, at least 1
+     */
+    pub stale_bytes: Option<u64>,
+
+    /**
+     * ! share of stale bytes in all records, above 0 and at most 1
+     */
+    pub stale_ratio: Option<f64>,
+
+    /**
+     * ! number of generation files in the directory, at least 2 since a compaction
+     * ! leaves the compacted gen and the active one
+     */
+    pub max_gens: Option<usize>,
+}
+
+impl CompactionPolicy {
+    pub const DEFAULT_STALE_BYTES: u64 = 1024 * 1024;
+
+    /**
+     * ! never compact on its own
+     */
+    pub fn manual() -> CompactionPolicy {
+        CompactionPolicy {
+            stale_bytes: None,
+            stale_ratio: None,
+            max_gens: None,
+        }
+    }
+
+    pub fn is_manual(&self) -> bool {
+        *self == CompactionPolicy::manual()
+    }
+
+    /**
+     * ! `KvError::InvalidOption` for a threshold that would compact on every write,
+     * ! `stale_bytes` of 0, `stale_ratio` outside (0, 1] or `max_gens` below 2
+     */
+    pub fn validate(&self) -> Result<()> {
+        if let Some(0) = self.stale_bytes {
+            return Err(KvError::InvalidOption("stale_bytes 0".to_owned()));
+        }
+        if let Some(ratio) = self
+            .stale_ratio
+            .filter(|ratio| !(*ratio > 0.0 && *ratio <= 1.0))
+        {
+            return Err(KvError::InvalidOption(format!("stale_ratio {}", ratio)));
+        }
+        if let Some(max) = self.max_gens.filter(|max| *max < 2) {
+            return Err(KvError::InvalidOption(format!("max_gens {}", max)));
+        }
+        Ok(())
+    }
+
+    /**
+     * ! whether a store with `stale` of `total` record bytes spread over `gens` files has to compact
+     */
+    pub(crate) fn should_compact(&self, stale: u64, total: u64, gens: usize) -> bool {
+        self.stale_bytes.is_some_and(|bytes| stale >= bytes)
+            || self
+                .stale_ratio
+                .is_some_and(|ratio| total > 0 && stale as f64 >= ratio * total as f64)
+            || self.max_gens.is_some_and(|max| gens > max)
+    }
+}
+
+/**
+ * ! compact once 1 MiB is stale
+ */
+impl Default for CompactionPolicy {
+    fn default() -> CompactionPolicy {
+        CompactionPolicy {
+            stale_bytes: Some(CompactionPolicy::DEFAULT_STALE_BYTES),
+            ..CompactionPolicy::manual()
+        }
+    }
+}
@@ -0,0 +1,145 @@
+use crate::error::KvError;
+use serde::{Deserialize, Serialize};
+use std::io;
+
+/**
+ * ! wire protocol between kvs-client and kvs-server
+ * * both sides exchange a stream of JSON values over one TCP connection,
+ * * the client writes a `Request` and the server answers with exactly one `Response`
+ */
+#[derive(Serialize, Deserialize, Debug)]
+pub enum Request {
+    Get { key: String },
+    Set { key: String, value: String },
+    Remove { key: String },
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+pub enum Response {
+    /**
+     * ! the request succeeded, carrying the value for a Get
+     */
+    Ok(Option<String>),
+    Err(RemoteError),
+}
+
+/**
+ * ! serializable mirror of `KvError`
+ * * errors without a serializable payload travel as their message
+ */
+#[derive(Serialize, Deserialize, Debug)]
+pub enum RemoteError {
+    Io(String),
+    File {
+        path: String,
+        message: String,
+    },
+    Serde(String),
+    KeyNotFound(String),
+    InvalidCommand,
+    Log {
+        gen: u64,
+        offset: u64,
+        message: String,
+    },
+    Corrupted {
+        gen: u64,
+        offset: u64,
+    },
+    InvalidManifest(String),
+    InvalidOption(String),
+    UnknownEngine(String),
+    WrongEngine {
+        expected: String,
+        found: String,
+    },
+    NotUtf8,
+    Conflict(String),
+    NotAnInteger(String),
+    Overflow(String),
+    KeyspaceNotFound(String),
+    KeyspaceExists(String),
+    InvalidKeyspace(String),
+    Locked(String),
+    ReadOnly,
+}
+
+impl From<KvError> for RemoteError {
+    fn from(e: KvError) -> RemoteError {
+        match e {
+            KvError::Io(e) => RemoteError::Io(e.to_string()),
+            KvError::File { path, source } => RemoteError::File {
+                path,
+                message: source.to_string(),
+            },
+            KvError::Serde(e) => RemoteError::Serde(e.to_string()),
+            KvError::KeyNotFound(key) => RemoteError::KeyNotFound(key),
+            KvError::InvalidCommand => RemoteError::InvalidCommand,
+            KvError::Log {
+                gen,
+                offset,
+                source,
+            } => RemoteError::Log {
+                gen,
+                offset,
+                message: source.to_string(),
+            },
+            KvError::Corrupted { gen, offset } => RemoteError::Corrupted { gen, offset },
+            KvError::InvalidManifest(line) => RemoteError::InvalidManifest(line),
+            KvError::InvalidOption(msg) => RemoteError::InvalidOption(msg),
+            KvError::UnknownEngine(name) => RemoteError::UnknownEngine(name),
+            KvError::WrongEngine { expected, found } => {
+                RemoteError::WrongEngine { expected, found }
+            }
+            KvError::NotUtf8 => RemoteError::NotUtf8,
+            KvError::Conflict(key) => RemoteError::Conflict(key),
+            KvError::NotAnInteger(key) => RemoteError::NotAnInteger(key),
+            KvError::Overflow(key) => RemoteError::Overflow(key),
+            KvError::KeyspaceNotFound(name) => RemoteError::KeyspaceNotFound(name),
+            KvError::KeyspaceExists(name) => RemoteError::KeyspaceExists(name),
+            KvError::InvalidKeyspace(name) => RemoteError::InvalidKeyspace(name),
+            KvError::Locked(path) => RemoteError::Locked(path),
+            KvError::ReadOnly => RemoteError::ReadOnly,
+        }
+    }
+}
+
+impl From<RemoteError> for KvError {
+    fn from(e: RemoteError) -> KvError {
+        match e {
+            RemoteError::Io(msg) => KvError::Io(io::Error::other(msg)),
+            RemoteError::File { path, message } => KvError::File {
+                path,
+                source: io::Error::other(message),
+            },
+            RemoteError::Serde(msg) => KvError::Serde(serde::de::Error::custom(msg)),
+            RemoteError::KeyNotFound(key) => KvError::KeyNotFound(key),
+            RemoteError::InvalidCommand => KvError::InvalidCommand,
+            RemoteError::Log {
+                gen,
+                offset,
+                message,
+            } => KvError::Log {
+                gen,
+                offset,
+                source: io::Error::other(message),
+            },
+            RemoteError::Corrupted { gen, offset } => KvError::Corrupted { gen, offset },
+            RemoteError::InvalidManifest(line) => KvError::InvalidManifest(line),
+            RemoteError::InvalidOption(msg) => KvError::InvalidOption(msg),
+            RemoteError::UnknownEngine(name) => KvError::UnknownEngine(name),
+            RemoteError::WrongEngine { expected, found } => {
+                KvError::WrongEngine { expected, found }
+            }
+            RemoteError::NotUtf8 => KvError::NotUtf8,
+            RemoteError::Conflict(key) => KvError::Conflict(key),
+            RemoteError::NotAnInteger(key) => KvError::NotAnInteger(key),
+            RemoteError::Overflow(key) => KvError::Overflow(key),
+            RemoteError::KeyspaceNotFound(name) => KvError::KeyspaceNotFound(name),
+            RemoteError::KeyspaceExists(name) => KvError::KeyspaceExists(name),
+            RemoteError::InvalidKeyspace(name) => KvError::InvalidKeyspace(name),
+            RemoteError::Locked(path) => KvError::Locked(path),
+            RemoteError::ReadOnly => KvError::ReadOnly,
+        }
+    }
+}
@@ -0,0 +1,445 @@
+use crate::error::{KvError, Result};
+use serde::Deserialize;
+use std::io::{self, Read, Write};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+/**
+ * ! every binary log file starts with these bytes, followed by the format version
+ * * log files without them are the old serde_json logs, one JSON Command after another
+ */
+pub(crate) const MAGIC: &[u8; 4] = b"KVLG";
+pub(crate) const FILE_HEADER_LEN: u64 = MAGIC.len() as u64 + 1;
+
+/**
+ * ! the binary format version
+ * * a record is a crc32 (u32 LE) of everything after it, then tag (u8), key length (u32 LE),
+ * * value length (u32 LE), key, value
+ * * a batch record has an empty key and the records of the batch as value, so its checksum
+ * * covers the whole batch and every record in it can still be read on its own at its
+ * * offset in the file
+ * * the value of an expiring set record is the expiry time (u64 LE, milliseconds since the
+ * * UNIX epoch) followed by the value that is set
+ * * a set or remove of a keyspace other than the default one has `KEYSPACE_FLAG` set in its
+ * * tag and the keyspace id (u32 LE) in front of its key
+ */
+pub(crate) const FORMAT_VERSION: u8 = 1;
+
+const CRC_LEN: usize = 4;
+const RECORD_HEADER_LEN: usize = 1 + 4 + 4;
+
+/**
+ * ! bytes a record takes besides its key and value
+ */
+pub(crate) const RECORD_OVERHEAD: u64 = (CRC_LEN + RECORD_HEADER_LEN) as u64;
+
+const TAG_SET: u8 = 1;
+const TAG_REMOVE: u8 = 2;
+const TAG_BATCH: u8 = 3;
+const TAG_SET_EXPIRING: u8 = 4;
+
+const KEYSPACE_FLAG: u8 = 0x80;
+
+const EXPIRY_LEN: usize = 8;
+const KEYSPACE_LEN: usize = 4;
+
+/**
+ * ! id of the keyspace every store has, see `KvStore::keyspace`
+ */
+pub(crate) const DEFAULT_KEYSPACE_ID: u32 = 0;
+
+/**
+ * ! one log record, keys and values are arbitrary bytes
+ * * `keyspace` is the id of the keyspace the key belongs to
+ */
+#[derive(Debug, PartialEq, Eq)]
+pub(crate) enum Command {
+    /**
+     * ! `expires` is when the key stops existing, in milliseconds since the UNIX epoch
+     */
+    Set {
+        keyspace: u32,
+        key: Vec<u8>,
+        value: Vec<u8>,
+        expires: Option<u64>,
+    },
+    Remove {
+        keyspace: u32,
+        key: Vec<u8>,
+    },
+    /**
+     * ! sets and removes that are written, and recovered, all together or not at all
+     */
+    Batch(Vec<Command>),
+}
+
+/**
+ * ! Command as the old JSON logs serialized it, only kept to read them
+ */
+#[derive(Deserialize, Debug)]
+pub(crate) enum JsonCommand {
+    Set { key: String, value: String },
+    Remove { key: String },
+}
+
+impl From<JsonCommand> for Command {
+    fn from(cmd: JsonCommand) -> Command {
+        match cmd {
+            JsonCommand::Set { key, value } => Command::Set {
+                keyspace: DEFAULT_KEYSPACE_ID,
+                key: key.into_bytes(),
+                value: value.into_bytes(),
+                expires: None,
+            },
+            JsonCommand::Remove { key } => Command::Remove {
+                keyspace: DEFAULT_KEYSPACE_ID,
+                key: key.into_bytes(),
+            },
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum LogFormat {
+    Json,
+    Binary,
+}
+
+impl LogFormat {
+    /**
+     * ! tell the format of a log file from its first bytes
+     * * an empty file has no records, so it counts as the binary format
+     * * `None` for a binary file of another format version
+     */
+    pub(crate) fn detect(head: &[u8]) -> Option<LogFormat> {
+        if head.is_empty() {
+            Some(LogFormat::Binary)
+        } else if head.starts_with(MAGIC) && head.len() > MAGIC.len() {
+            (head[MAGIC.len()] == FORMAT_VERSION).then_some(LogFormat::Binary)
+        } else {
+            Some(LogFormat::Json)
+        }
+    }
+
+    /**
+     * ! whether the file has to be rewritten by compaction to reach the binary format
+     */
+    pub(crate) fn is_legacy(self) -> bool {
+        self == LogFormat::Json
+    }
+
+    /**
+     * ! where the first record starts
+     */
+    pub(crate) fn data_start(self, file_len: u64) -> u64 {
+        match self {
+            LogFormat::Binary => FILE_HEADER_LEN.min(file_len),
+            LogFormat::Json => 0,
+        }
+    }
+}
+
+pub(crate) fn write_file_header<W: Write>(writer: &mut W) -> Result<()> {
+    writer.write_all(MAGIC)?;
+    writer.write_all(&[FORMAT_VERSION])?;
+    Ok(())
+}
+
+impl Command {
+    /**
+     * ! the same command for the keys of `keyspace`, a batch moves all its commands
+     */
+    pub(crate) fn with_keyspace(self, keyspace: u32) -> Command {
+        match self {
+            Command::Set {
+                key,
+                value,
+                expires,
+                ..
+            } => Command::Set {
+                keyspace,
+                key,
+                value,
+                expires,
+            },
+            Command::Remove { key, .. } => Command::Remove { keyspace, key },
+            Command::Batch(cmds) => Command::Batch(
+                cmds.into_iter()
+                    .map(|cmd| cmd.with_keyspace(keyspace))
+                    .collect(),
+            ),
+        }
+    }
+
+    /**
+     * ! write the command as one binary record, returning its length in bytes
+     */
+    pub(crate) fn write_to<W: Write>(&self, writer: &mut W) -> Result<u64> {
+        let body;
+        let prefixed;
+        let (tag, keyspace, key, value): (u8, u32, &[u8], &[u8]) = match self {
+            Command::Set {
+                keyspace,
+                key,
+                value,
+                expires: None,
+            } => (TAG_SET, *keyspace, key, value),
+            Command::Set {
+                keyspace,
+                key,
+                value,
+                expires: Some(expires),
+            } => {
+                let mut buf = Vec::with_capacity(EXPIRY_LEN + value.len());
+                buf.extend_from_slice(&expires.to_le_bytes());
+                buf.extend_from_slice(value);
+                body = buf;
+                (TAG_SET_EXPIRING, *keyspace, key, &body)
+            }
+            Command::Remove { keyspace, key } => (TAG_REMOVE, *keyspace, key, &[]),
+            Command::Batch(cmds) => {
+                let mut buf = Vec::new();
+                for cmd in cmds {
+                    cmd.write_to(&mut buf)?;
+                }
+                body = buf;
+                (TAG_BATCH, DEFAULT_KEYSPACE_ID, &[], &body)
+            }
+        };
+        let (tag, key) = if keyspace == DEFAULT_KEYSPACE_ID {
+            (tag, key)
+        } else {
+            let mut buf = Vec::with_capacity(KEYSPACE_LEN + key.len());
+            buf.extend_from_slice(&keyspace.to_le_bytes());
+            buf.extend_from_slice(key);
+            prefixed = buf;
+            (tag | KEYSPACE_FLAG, prefixed.as_slice())
+        };
+        let mut header = [0; RECORD_HEADER_LEN];
+        header[0] = tag;
+        header[1..5].copy_from_slice(&(key.len() as u32).to_le_bytes());
+        header[5..9].copy_from_slice(&(value.len() as u32).to_le_bytes());
+
+        let mut hasher = crc32fast::Hasher::new();
+        hasher.update(&header);
+        hasher.update(key);
+        hasher.update(value);
+
+        writer.write_all(&hasher.finalize().to_le_bytes())?;
+        writer.write_all(&header)?;
+        writer.write_all(key)?;
+        writer.write_all(value)?;
+        Ok(RECORD_OVERHEAD + (key.len() + value.len()) as u64)
+    }
+
+    /**
+     * ! the length `write_to` writes
+     */
+    pub(crate) fn encoded_len(&self) -> u64 {
+        let keyspace_len = |keyspace: &u32| {
+            if *keyspace == DEFAULT_KEYSPACE_ID {
+                0
+            } else {
+                KEYSPACE_LEN
+            }
+        };
+        RECORD_OVERHEAD
+            + match self {
+                Command::Set {
+                    keyspace,
+                    key,
+                    value,
+                    expires,
+                } => {
+                    let expiry_len = if expires.is_some() { EXPIRY_LEN } else { 0 };
+                    (keyspace_len(keyspace) + key.len() + value.len() + expiry_len) as u64
+                }
+                Command::Remove { keyspace, key } => (keyspace_len(keyspace) + key.len()) as u64,
+                Command::Batch(cmds) => cmds.iter().map(Command::encoded_len).sum(),
+            }
+    }
+}
+
+/**
+ * ! reads the binary records of one generation between `pos` and `end`
+ * * anything that does not decode into a record (bad checksum, unknown tag,
+ * * a length running past `end`) is reported as corruption at the record's offset
+ * * a failing record that reaches `end` is remembered as torn, see `torn`
+ */
+pub(crate) struct RecordReader<R> {
+    reader: R,
+    gen: u64,
+    pos: u64,
+    end: u64,
+    torn: bool,
+}
+
+impl<R: Read> RecordReader<R> {
+    pub(crate) fn new(reader: R, gen: u64, pos: u64, end: u64) -> RecordReader<R> {
+        RecordReader {
+            reader,
+            gen,
+            pos,
+            end,
+            torn: false,
+        }
+    }
+
+    /**
+     * ! whether the last corrupted record was cut off by `end`
+     * * that is what a crash in the middle of appending a record leaves behind, as opposed to
+     * * a damaged record with more data after it
+     */
+    pub(crate) fn torn(&self) -> bool {
+        self.torn
+    }
+
+    /**
+     * ! the next command with its offset and length, `None` once `end` is reached
+     */
+    pub(crate) fn next_record(&mut self) -> Result<Option<(Command, u64, u64)>> {
+        if self.pos >= self.end {
+            return Ok(None);
+        }
+        let offset = self.pos;
+
+        let mut header = [0; CRC_LEN + RECORD_HEADER_LEN];
+        self.read_exact(&mut header)?;
+        let (crc, header) = header.split_at(CRC_LEN);
+
+        let key_len = u32::from_le_bytes([header[1], header[2], header[3], header[4]]) as u64;
+        let value_len = u32::from_le_bytes([header[5], header[6], header[7], header[8]]) as u64;
+        if self.pos + key_len + value_len > self.end {
+            self.torn = true;
+            return Err(self.corrupted(offset));
+        }
+        let mut key = vec![0; key_len as usize];
+        self.read_exact(&mut key)?;
+        let mut value = vec![0; value_len as usize];
+        self.read_exact(&mut value)?;
+
+        let mut hasher = crc32fast::Hasher::new();
+        hasher.update(header);
+        hasher.update(&key);
+        hasher.update(&value);
+        if hasher.finalize().to_le_bytes() != crc {
+            // ! a record written out of order can end at the right place with garbage in it
+            self.torn = self.pos == self.end;
+            return Err(self.corrupted(offset));
+        }
+
+        let mut tag = header[0];
+        let mut keyspace = DEFAULT_KEYSPACE_ID;
+        if tag & KEYSPACE_FLAG != 0 {
+            if key.len() < KEYSPACE_LEN || tag & !KEYSPACE_FLAG == TAG_BATCH {
+                return Err(self.corrupted(offset));
+            }
+            let mut id = [0; KEYSPACE_LEN];
+            id.copy_from_slice(&key[..KEYSPACE_LEN]);
+            key.drain(..KEYSPACE_LEN);
+            keyspace = u32::from_le_bytes(id);
+            tag &= !KEYSPACE_FLAG;
+        }
+
+        let cmd = match tag {
+            TAG_SET => Command::Set {
+                keyspace,
+                key,
+                value,
+                expires: None,
+            },
+            TAG_SET_EXPIRING if value.len() >= EXPIRY_LEN => {
+                let mut expires = [0; EXPIRY_LEN];
+                expires.copy_from_slice(&value[..EXPIRY_LEN]);
+                Command::Set {
+                    keyspace,
+                    key,
+                    value: value[EXPIRY_LEN..].to_vec(),
+                    expires: Some(u64::from_le_bytes(expires)),
+                }
+            }
+            TAG_REMOVE => Command::Remove { keyspace, key },
+            TAG_BATCH => {
+                let start = self.pos - value_len;
+                Command::Batch(self.read_batch(&value, start)?)
+            }
+            _ => return Err(self.corrupted(offset)),
+        };
+        Ok(Some((cmd, offset, self.pos - offset)))
+    }
+
+    /**
+     * ! the records of a batch whose body is `body`, found at `start` of the file
+     */
+    fn read_batch(&self, body: &[u8], start: u64) -> Result<Vec<Command>> {
+        let end = start + body.len() as u64;
+        let mut records = RecordReader::new(body, self.gen, start, end);
+        let mut cmds = Vec::new();
+        while let Some((cmd, offset, _)) = records.next_record()? {
+            if let Command::Batch(_) = cmd {
+                return Err(self.corrupted(offset));
+            }
+            cmds.push(cmd);
+        }
+        Ok(cmds)
+    }
+
+    fn read_exact(&mut self, buf: &mut [u8]) -> Result<()> {
+        let offset = self.pos;
+        match self.reader.read_exact(buf) {
+            Ok(()) => {
+                self.pos += buf.len() as u64;
+                Ok(())
+            }
+            Err(ref e) if e.kind() == io::ErrorKind::UnexpectedEof => {
+                self.torn = true;
+                Err(self.corrupted(offset))
+            }
+            Err(e) => Err(KvError::Log {
+                gen: self.gen,
+                offset,
+                source: e,
+            }),
+        }
+    }
+
+    fn corrupted(&self, offset: u64) -> KvError {
+        KvError::Corrupted {
+            gen: self.gen,
+            offset,
+        }
+    }
+}
+
+/**
+ * ! the time expiry times are compared with, in milliseconds since the UNIX epoch
+ */
+pub(crate) fn now_millis() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |now| now.as_millis() as u64)
+}
+
+/**
+ * ! the expiry time of a key set now to live for `ttl`
+ */
+pub(crate) fn expiry(ttl: Duration) -> u64 {
+    now_millis().saturating_add(ttl.as_millis() as u64)
+}
+
+/**
+ * ! decode the single record of the given format stored in `bytes` at `offset` of gen
+ */
+pub(crate) fn decode(format: LogFormat, bytes: &[u8], gen: u64, offset: u64) -> Result<Command> {
+    match format {
+        LogFormat::Json => serde_json::from_slice::<JsonCommand>(bytes)
+            .map(Command::from)
+            .map_err(|_| KvError::Corrupted { gen, offset }),
+        LogFormat::Binary => {
+            let end = offset + bytes.len() as u64;
+            match RecordReader::new(bytes, gen, offset, end).next_record()? {
+                Some((cmd, _, _)) => Ok(cmd),
+                None => Err(KvError::Corrupted { gen, offset }),
+            }
+        }
+    }
+}
@@ -0,0 +1,160 @@
+use crate::engine::KvsEngine;
+use crate::error::Result;
+use std::ops::{Bound, RangeBounds};
+
+/**
+ * ! what a `Scan` walks through: any engine, or a `Snapshot` of a `KvStore`
+ */
+pub trait ScanSource: Clone {
+    /**
+     * ! the pair with the smallest key in `range`, or the largest one if `reverse`,
+     * ! `None` if no key falls in `range`
+     */
+    fn seek_bytes(
+        &self,
+        range: (Bound<&[u8]>, Bound<&[u8]>),
+        reverse: bool,
+    ) -> Result<Option<(Vec<u8>, Vec<u8>)>>;
+}
+
+impl<E: KvsEngine> ScanSource for E {
+    fn seek_bytes(
+        &self,
+        range: (Bound<&[u8]>, Bound<&[u8]>),
+        reverse: bool,
+    ) -> Result<Option<(Vec<u8>, Vec<u8>)>> {
+        KvsEngine::seek_bytes(self, range, reverse)
+    }
+}
+
+/**
+ * ! ordered iterator over the key value pairs of a range, see `KvsEngine::scan_bytes`
+ * * every step looks up the next key past the last one returned, so the iterator owns
+ * * no borrow of what it walks, a scan of an engine sees writes made while it runs
+ * * it is double-ended: `.rev()` walks the range backwards and `.take(limit)` stops early,
+ * * both ends narrow the same range, so they never return a key twice
+ */
+pub struct Scan<E> {
+    source: E,
+    start: Bound<Vec<u8>>,
+    end: Bound<Vec<u8>>,
+    done: bool,
+}
+
+impl<E: ScanSource> Scan<E> {
+    pub(crate) fn new<R: RangeBounds<Vec<u8>>>(source: E, range: R) -> Scan<E> {
+        Scan {
+            source,
+            start: range.start_bound().cloned(),
+            end: range.end_bound().cloned(),
+            done: false,
+        }
+    }
+
+    /**
+     * ! all keys starting with `prefix`
+     */
+    pub(crate) fn prefix(source: E, prefix: &[u8]) -> Scan<E> {
+        let end = match prefix_end(prefix) {
+            Some(end) => Bound::Excluded(end),
+            None => Bound::Unbounded,
+        };
+        Scan::new(source, (Bound::Included(prefix.to_vec()), end))
+    }
+
+    fn step(&mut self, reverse: bool) -> Option<Result<(Vec<u8>, Vec<u8>)>> {
+        if self.done {
+            return None;
+        }
+        let range = (
+            self.start.as_ref().map(Vec::as_slice),
+            self.end.as_ref().map(Vec::as_slice),
+        );
+        match self.source.seek_bytes(range, reverse) {
+            Ok(Some((key, value))) => {
+                if reverse {
+                    self.end = Bound::Excluded(key.clone());
+                } else {
+                    self.start = Bound::Excluded(key.clone());
+                }
+                Some(Ok((key, value)))
+            }
+            Ok(None) => {
+                self.done = true;
+                None
+            }
+            Err(e) => {
+                self.done = true;
+                Some(Err(e))
+            }
+        }
+    }
+}
+
+impl<E: ScanSource> Iterator for Scan<E> {
+    type Item = Result<(Vec<u8>, Vec<u8>)>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.step(false)
+    }
+}
+
+impl<E: ScanSource> DoubleEndedIterator for Scan<E> {
+    fn next_back(&mut self) -> Option<Self::Item> {
+        self.step(true)
+    }
+}
+
+/**
+ * ! `Scan` over text keys and values, see `KvsEngine::scan`
+ * * a pair that is not valid UTF-8 comes out as `KvError::NotUtf8`
+ */
+pub struct StrScan<E>(Scan<E>);
+
+impl<E: ScanSource> StrScan<E> {
+    pub(crate) fn new(scan: Scan<E>) -> StrScan<E> {
+        StrScan(scan)
+    }
+}
+
+fn to_text(pair: Result<(Vec<u8>, Vec<u8>)>) -> Result<(String, String)> {
+    let (key, value) = pair?;
+    Ok((String::from_utf8(key)?, String::from_utf8(value)?))
+}
+
+impl<E: ScanSource> Iterator for StrScan<E> {
+    type Item = Result<(String, String)>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.0.next().map(to_text)
+    }
+}
+
+impl<E: ScanSource> DoubleEndedIterator for StrScan<E> {
+    fn next_back(&mut self) -> Option<Self::Item> {
+        self.0.next_back().map(to_text)
+    }
+}
+
+/**
+ * ! a range of text keys as the range of their bytes, UTF-8 sorts like the bytes it is made of
+ */
+pub(crate) fn byte_range<R: RangeBounds<String>>(range: R) -> (Bound<Vec<u8>>, Bound<Vec<u8>>) {
+    let bytes = |bound: Bound<&String>| bound.map(|key| key.clone().into_bytes());
+    (bytes(range.start_bound()), bytes(range.end_bound()))
+}
+
+/**
+ * ! the smallest key greater than every key starting with `prefix`,
+ * ! `None` if there is none because the prefix is empty or all 0xff
+ */
+fn prefix_end(prefix: &[u8]) -> Option<Vec<u8>> {
+    let mut end = prefix.to_vec();
+    while let Some(last) = end.pop() {
+        if last < u8::MAX {
+            end.push(last + 1);
+            return Some(end);
+        }
+    }
+    None
+}
@@ -0,0 +1,71 @@
+use crate::engine::KvsEngine;
+use crate::error::Result;
+use crate::protocol::{Request, Response};
+use log::{debug, error};
+use serde_json::Deserializer;
+use std::io::{BufReader, BufWriter, Write};
+use std::net::{TcpListener, TcpStream, ToSocketAddrs};
+use std::thread;
+
+/**
+ * ! server side of the wire protocol
+ * * every connection is served on its own thread with its own clone of the engine
+ */
+pub struct KvsServer<E: KvsEngine> {
+    engine: E,
+}
+
+impl<E: KvsEngine> KvsServer<E> {
+    pub fn new(engine: E) -> KvsServer<E> {
+        KvsServer { engine }
+    }
+
+    /**
+     * ! accept connections on `addr` until the listener fails
+     * * an error on one connection is logged and does not stop the server
+     */
+    pub fn run(self, addr: impl ToSocketAddrs) -> Result<()> {
+        let listener = TcpListener::bind(addr)?;
+        for stream in listener.incoming() {
+            match stream {
+                Ok(stream) => {
+                    let engine = self.engine.clone();
+                    thread::spawn(move || {
+                        if let Err(e) = serve(engine, stream) {
+                            error!("Error on serving client: {}", e);
+                        }
+                    });
+                }
+                Err(e) => error!("Connection failed: {}", e),
+            }
+        }
+        Ok(())
+    }
+}
+
+fn serve<E: KvsEngine>(engine: E, stream: TcpStream) -> Result<()> {
+    let peer = stream.peer_addr()?;
+    let reader = BufReader::new(stream.try_clone()?);
+    let mut writer = BufWriter::new(stream);
+
+    for req in Deserializer::from_reader(reader).into_iter::<Request>() {
+        let req = req?;
+        debug!("Receive request from {}: {:?}", peer, req);
+        let resp = match handle(&engine, req) {
+            Ok(value) => Response::Ok(value),
+            Err(e) => Response::Err(e.into()),
+        };
+        serde_json::to_writer(&mut writer, &resp)?;
+        writer.flush()?;
+        debug!("Response sent to {}: {:?}", peer, resp);
+    }
+    Ok(())
+}
+
+fn handle<E: KvsEngine>(engine: &E, req: Request) -> Result<Option<String>> {
+    match req {
+        Request::Get { key } => engine.get(key),
+        Request::Set { key, value } => engine.set(key, value).map(|_| None),
+        Request::Remove { key } => engine.remove(key).map(|_| None),
+    }
+}
@@ -0,0 +1,69 @@
+use crate::error::Result;
+use crate::kvs::{KvStore, ReadPoint};
+use crate::scan::{byte_range, Scan, ScanSource, StrScan};
+use std::ops::{Bound, RangeBounds};
+use std::sync::Arc;
+
+/**
+ * ! read-only view of a `KvStore` frozen at the moment `KvStore::snapshot` was called
+ * * writes made afterwards are invisible to it, so a long export reads one consistent state
+ * * while the store keeps taking writes
+ * * compaction keeps every generation a live snapshot still reads from, they are deleted
+ * * by the first compaction after the last clone of the snapshot is dropped
+ */
+#[derive(Clone)]
+pub struct Snapshot {
+    store: KvStore,
+    point: Arc<ReadPoint>,
+}
+
+impl Snapshot {
+    pub(crate) fn new(store: KvStore, point: Arc<ReadPoint>) -> Snapshot {
+        Snapshot { store, point }
+    }
+
+    pub(crate) fn store(&self) -> &KvStore {
+        &self.store
+    }
+
+    pub(crate) fn point(&self) -> &ReadPoint {
+        &self.point
+    }
+
+    pub fn get_bytes(&self, key: &[u8]) -> Result<Option<Vec<u8>>> {
+        self.store.get_at(&self.point, key)
+    }
+
+    pub fn get(&self, key: String) -> Result<Option<String>> {
+        match self.get_bytes(key.as_bytes())? {
+            Some(value) => Ok(Some(String::from_utf8(value)?)),
+            None => Ok(None),
+        }
+    }
+
+    pub fn scan_bytes<R: RangeBounds<Vec<u8>>>(&self, range: R) -> Scan<Snapshot> {
+        Scan::new(self.clone(), range)
+    }
+
+    pub fn scan_prefix_bytes(&self, prefix: &[u8]) -> Scan<Snapshot> {
+        Scan::prefix(self.clone(), prefix)
+    }
+
+    pub fn scan<R: RangeBounds<String>>(&self, range: R) -> StrScan<Snapshot> {
+        StrScan::new(self.scan_bytes(byte_range(range)))
+    }
+
+    pub fn scan_prefix(&self, prefix: &str) -> StrScan<Snapshot> {
+        StrScan::new(self.scan_prefix_bytes(prefix.as_bytes()))
+    }
+}
+
+impl ScanSource for Snapshot {
+    fn seek_bytes(
+        &self,
+        range: (Bound<&[u8]>, Bound<&[u8]>),
+        reverse: bool,
+    ) -> Result<Option<(Vec<u8>, Vec<u8>)>> {
+        self.store.seek_at(&self.point, range, reverse)
+    }
+}
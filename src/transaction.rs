@@ -0,0 +1,88 @@
+use crate::batch::WriteBatch;
+use crate::error::{KvError, Result};
+use crate::snapshot::Snapshot;
+use std::collections::{BTreeMap, BTreeSet};
+
+/**
+ * ! a read-modify-write transaction on a `KvStore` with snapshot isolation
+ * * reads see the store as it was at `KvStore::begin` plus the transaction's own writes,
+ * * whatever other writers do meanwhile
+ * * writes are buffered until `commit`, which applies them as one `WriteBatch`
+ * * `commit` fails with `KvError::Conflict` if another writer set or removed a key the
+ * * transaction read or wrote since it began, and then applies nothing
+ * * dropping a transaction without committing rolls it back
+ */
+pub struct Transaction {
+    snapshot: Snapshot,
+    reads: BTreeSet<Vec<u8>>,
+    // ! None for a remove
+    writes: BTreeMap<Vec<u8>, Option<Vec<u8>>>,
+}
+
+impl Transaction {
+    pub(crate) fn new(snapshot: Snapshot) -> Transaction {
+        Transaction {
+            snapshot,
+            reads: BTreeSet::new(),
+            writes: BTreeMap::new(),
+        }
+    }
+
+    pub fn get_bytes(&mut self, key: &[u8]) -> Result<Option<Vec<u8>>> {
+        if let Some(value) = self.writes.get(key) {
+            return Ok(value.clone());
+        }
+        self.reads.insert(key.to_vec());
+        self.snapshot.get_bytes(key)
+    }
+
+    pub fn set_bytes(&mut self, key: &[u8], value: &[u8]) {
+        self.writes.insert(key.to_vec(), Some(value.to_vec()));
+    }
+
+    /**
+     * ! `KvError::KeyNotFound` if the key does not exist as the transaction sees the store
+     */
+    pub fn remove_bytes(&mut self, key: &[u8]) -> Result<()> {
+        if self.get_bytes(key)?.is_none() {
+            return Err(KvError::key_not_found(key));
+        }
+        self.writes.insert(key.to_vec(), None);
+        Ok(())
+    }
+
+    pub fn get(&mut self, key: String) -> Result<Option<String>> {
+        match self.get_bytes(key.as_bytes())? {
+            Some(value) => Ok(Some(String::from_utf8(value)?)),
+            None => Ok(None),
+        }
+    }
+
+    pub fn set(&mut self, key: String, value: String) {
+        self.set_bytes(key.as_bytes(), value.as_bytes())
+    }
+
+    pub fn remove(&mut self, key: String) -> Result<()> {
+        self.remove_bytes(key.as_bytes())
+    }
+
+    /**
+     * ! apply the buffered writes atomically, a transaction that wrote nothing has nothing to check
+     */
+    pub fn commit(self) -> Result<()> {
+        if self.writes.is_empty() {
+            return Ok(());
+        }
+        let mut batch = WriteBatch::new();
+        for (key, value) in &self.writes {
+            match value {
+                Some(value) => batch.set_bytes(key, value),
+                None => batch.remove_bytes(key),
+            }
+        }
+        let keys = self.reads.iter().chain(self.writes.keys());
+        self.snapshot
+            .store()
+            .commit(self.snapshot.point(), keys.map(Vec::as_slice), batch)
+    }
+}
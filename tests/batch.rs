@@ -0,0 +1,87 @@
+mod common;
+
+use common::log_files;
+use kv::error::KvError;
+use kv::{KvStore, KvsEngine, MemStore, Result, WriteBatch};
+use std::fs::{self, OpenOptions};
+use tempfile::TempDir;
+
+fn check_batch<E: KvsEngine>(engine: E) -> Result<()> {
+    engine.set("key1".to_owned(), "value1".to_owned())?;
+    engine.set("key2".to_owned(), "value2".to_owned())?;
+
+    let mut batch = WriteBatch::new();
+    batch.set("key3".to_owned(), "value3".to_owned());
+    batch.remove("key1".to_owned());
+    batch.set("key2".to_owned(), "value4".to_owned());
+    batch.set_bytes(b"key5", b"value5");
+    batch.remove_bytes(b"key5");
+    assert_eq!(batch.len(), 5);
+    engine.write(batch)?;
+
+    assert_eq!(engine.get("key1".to_owned())?, None);
+    assert_eq!(engine.get("key2".to_owned())?, Some("value4".to_owned()));
+    assert_eq!(engine.get("key3".to_owned())?, Some("value3".to_owned()));
+    assert_eq!(engine.get("key5".to_owned())?, None);
+
+    // a remove of a missing key fails the batch before any of it is applied
+    let mut batch = WriteBatch::new();
+    batch.set("key6".to_owned(), "value6".to_owned());
+    batch.remove("key1".to_owned());
+    assert!(matches!(engine.write(batch), Err(KvError::KeyNotFound(_))));
+    assert_eq!(engine.get("key6".to_owned())?, None);
+
+    engine.write(WriteBatch::new())?;
+    Ok(())
+}
+
+#[test]
+fn kvs_engine_batch() -> Result<()> {
+    let temp_dir = TempDir::new().expect("unable to create temporary working directory");
+    check_batch(KvStore::open(temp_dir.path())?)?;
+
+    let store = KvStore::open(temp_dir.path())?;
+    assert_eq!(store.get("key1".to_owned())?, None);
+    assert_eq!(store.get("key2".to_owned())?, Some("value4".to_owned()));
+    assert_eq!(store.get("key3".to_owned())?, Some("value3".to_owned()));
+    assert_eq!(store.get("key6".to_owned())?, None);
+
+    store.compaction()?;
+    assert_eq!(store.get("key2".to_owned())?, Some("value4".to_owned()));
+    assert_eq!(store.get("key3".to_owned())?, Some("value3".to_owned()));
+    Ok(())
+}
+
+#[test]
+fn memory_engine_batch() -> Result<()> {
+    check_batch(MemStore::new())
+}
+
+// A batch cut off by a crash is dropped as a whole on open.
+#[test]
+fn torn_batch_discarded() -> Result<()> {
+    let temp_dir = TempDir::new().expect("unable to create temporary working directory");
+    let store = KvStore::open(temp_dir.path())?;
+    store.set("key1".to_owned(), "value1".to_owned())?;
+    let mut batch = WriteBatch::new();
+    for i in 0..10 {
+        batch.set(format!("key{}", i), format!("batch{}", i));
+    }
+    store.write(batch)?;
+    drop(store);
+
+    let log = log_files(temp_dir.path()).pop().unwrap();
+    let len = fs::metadata(&log)?.len();
+    // cut off the end of the batch, the records before the cut are still intact
+    OpenOptions::new()
+        .write(true)
+        .open(&log)?
+        .set_len(len - 3)?;
+
+    let store = KvStore::open(temp_dir.path())?;
+    assert_eq!(store.get("key1".to_owned())?, Some("value1".to_owned()));
+    for i in (0..10).filter(|&i| i != 1) {
+        assert_eq!(store.get(format!("key{}", i))?, None);
+    }
+    Ok(())
+}
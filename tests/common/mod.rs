@@ -0,0 +1,44 @@
+// Helpers shared by the integration tests, each test crate uses only some of them.
+#![allow(dead_code)]
+
+use assert_cmd::prelude::*;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+
+// The `kv` binary run in `cwd`, unaffected by the environment of the test run.
+pub fn kv(cwd: &Path) -> Command {
+    let mut cmd = Command::cargo_bin("kv").unwrap();
+    cmd.current_dir(cwd)
+        .env_remove("KV_DATA_DIR")
+        .env_remove("KV_CONFIG");
+    cmd
+}
+
+// The `<gen>.<ext>` files in `dir`, oldest generation first.
+pub fn files_with_extension(dir: &Path, ext: &str) -> Vec<PathBuf> {
+    let mut files: Vec<PathBuf> = fs::read_dir(dir)
+        .unwrap()
+        .map(|entry| entry.unwrap().path())
+        .filter(|path| path.extension() == Some(ext.as_ref()))
+        .collect();
+    files.sort_by_key(|path| {
+        path.file_stem()
+            .and_then(|stem| stem.to_str())
+            .and_then(|stem| stem.parse::<u64>().ok())
+    });
+    files
+}
+
+// The log files in `dir`, oldest generation first.
+pub fn log_files(dir: &Path) -> Vec<PathBuf> {
+    files_with_extension(dir, "log")
+}
+
+// The size of all log files in `dir`.
+pub fn log_bytes(dir: &Path) -> u64 {
+    log_files(dir)
+        .iter()
+        .map(|path| fs::metadata(path).unwrap().len())
+        .sum()
+}
@@ -0,0 +1,460 @@
+mod common;
+
+use assert_cmd::prelude::*;
+use common::log_files;
+use kv::error::KvError;
+use kv::{CompactionPolicy, KvStore, Options, Result};
+use std::collections::HashMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+use std::thread;
+use std::time::Duration;
+use tempfile::TempDir;
+
+fn files(dir: &Path) -> Vec<PathBuf> {
+    let mut files: Vec<PathBuf> = fs::read_dir(dir)
+        .unwrap()
+        .map(|entry| entry.unwrap().path())
+        .collect();
+    files.sort();
+    files
+}
+
+fn logs(dir: &Path) -> HashMap<PathBuf, Vec<u8>> {
+    log_files(dir)
+        .into_iter()
+        .map(|path| {
+            let content = fs::read(&path).unwrap();
+            (path, content)
+        })
+        .collect()
+}
+
+// Fill a store, remove a key, and compact it. Returns the logs as they were before compaction.
+fn compacted_store(dir: &Path) -> Result<HashMap<PathBuf, Vec<u8>>> {
+    let store = KvStore::open(dir)?;
+    for i in 0..10 {
+        store.set(format!("key{}", i), format!("value{}", i))?;
+    }
+    store.remove("key0".to_owned())?;
+    let before = logs(dir);
+    store.compaction()?;
+    Ok(before)
+}
+
+fn check_content(dir: &Path) -> Result<()> {
+    let store = KvStore::open(dir)?;
+    assert_eq!(store.get("key0".to_owned())?, None);
+    for i in 1..10 {
+        assert_eq!(store.get(format!("key{}", i))?, Some(format!("value{}", i)));
+    }
+    Ok(())
+}
+
+// Stale generations a crash left behind after the manifest was written are not loaded.
+#[test]
+fn stale_generations_removed_on_open() -> Result<()> {
+    let temp_dir = TempDir::new().expect("unable to create temporary working directory");
+    let before = compacted_store(temp_dir.path())?;
+
+    for (path, content) in &before {
+        fs::write(path, content)?;
+    }
+    check_content(temp_dir.path())?;
+    for path in before.keys() {
+        assert!(!path.exists(), "{:?} not cleaned up", path);
+    }
+    Ok(())
+}
+
+// A compaction that crashed before writing the manifest leaves only temp files, which are dropped.
+#[test]
+fn uncommitted_compaction_discarded() -> Result<()> {
+    let temp_dir = TempDir::new().expect("unable to create temporary working directory");
+    let store = KvStore::open(temp_dir.path())?;
+    for i in 0..10 {
+        store.set(format!("key{}", i), format!("value{}", i))?;
+    }
+    store.remove("key0".to_owned())?;
+    drop(store);
+
+    fs::write(temp_dir.path().join("100.log.tmp"), b"half written")?;
+    fs::write(temp_dir.path().join("100.hint"), b"half written")?;
+    fs::write(temp_dir.path().join("MANIFEST.tmp"), b"100\n")?;
+
+    check_content(temp_dir.path())?;
+    for path in files(temp_dir.path()) {
+        let name = path.file_name().unwrap().to_str().unwrap().to_owned();
+        assert!(
+            !name.ends_with(".tmp") && name != "100.hint",
+            "{} left",
+            name
+        );
+    }
+    Ok(())
+}
+
+// A compaction that crashed after writing the manifest is finished by the next open.
+#[test]
+fn committed_compaction_rolled_forward() -> Result<()> {
+    let temp_dir = TempDir::new().expect("unable to create temporary working directory");
+    let before = compacted_store(temp_dir.path())?;
+
+    // undo the rename and the deletion of the stale gens
+    let manifest = fs::read_to_string(temp_dir.path().join("MANIFEST"))?;
+    let compacted: u64 = manifest.lines().next().unwrap().parse().unwrap();
+    let compacted_log = temp_dir.path().join(format!("{}.log", compacted));
+    fs::rename(
+        &compacted_log,
+        temp_dir.path().join(format!("{}.log.tmp", compacted)),
+    )?;
+    for (path, content) in &before {
+        fs::write(path, content)?;
+    }
+
+    check_content(temp_dir.path())?;
+    assert!(compacted_log.exists());
+    Ok(())
+}
+
+// Compaction kicks in on its own while writes keep going, and every last write survives it.
+#[test]
+fn background_compaction() -> Result<()> {
+    let temp_dir = TempDir::new().expect("unable to create temporary working directory");
+    let store = KvStore::open(temp_dir.path())?;
+    let value = "v".repeat(1000);
+    for iter in 0..5 {
+        for i in 0..1000 {
+            store.set(format!("key{}", i), format!("{}{}", value, iter))?;
+            if i % 100 == 0 {
+                assert_eq!(
+                    store.get(format!("key{}", i))?,
+                    Some(format!("{}{}", value, iter))
+                );
+            }
+        }
+    }
+    drop(store);
+
+    assert!(temp_dir.path().join("MANIFEST").exists());
+    let size: u64 = logs(temp_dir.path())
+        .values()
+        .map(|log| log.len() as u64)
+        .sum();
+    assert!(size < 5 * 1000 * 1000, "logs were never compacted");
+
+    let store = KvStore::open(temp_dir.path())?;
+    for i in 0..1000 {
+        assert_eq!(store.get(format!("key{}", i))?, Some(format!("{}4", value)));
+    }
+    Ok(())
+}
+
+fn open_with_policy(dir: &Path, compaction: CompactionPolicy) -> Result<KvStore> {
+    KvStore::open_with_options(
+        dir,
+        Options {
+            compaction,
+            ..Options::default()
+        },
+    )
+}
+
+// A manual-only store never compacts on its own, however much of it is stale.
+#[test]
+fn manual_compaction_policy() -> Result<()> {
+    let temp_dir = TempDir::new().expect("unable to create temporary working directory");
+    let store = open_with_policy(temp_dir.path(), CompactionPolicy::manual())?;
+    let value = "v".repeat(1000);
+    for _ in 0..3 {
+        for i in 0..1000 {
+            store.set(format!("key{}", i), value.clone())?;
+        }
+    }
+    drop(store);
+    assert!(!temp_dir.path().join("MANIFEST").exists());
+    assert_eq!(logs(temp_dir.path()).len(), 1);
+    Ok(())
+}
+
+// Removed keys and the remove records themselves count as stale.
+#[test]
+fn removes_count_as_stale() -> Result<()> {
+    let temp_dir = TempDir::new().expect("unable to create temporary working directory");
+    let policy = CompactionPolicy {
+        stale_bytes: Some(400),
+        ..CompactionPolicy::manual()
+    };
+    let store = open_with_policy(temp_dir.path(), policy)?;
+    for i in 0..10 {
+        store.set(format!("key{}", i), format!("value{}", i))?;
+    }
+    for i in 0..10 {
+        store.remove(format!("key{}", i))?;
+    }
+    drop(store);
+
+    assert!(temp_dir.path().join("MANIFEST").exists());
+    let store = KvStore::open(temp_dir.path())?;
+    assert_eq!(store.scan(..).count(), 0);
+    Ok(())
+}
+
+// A compaction that failed leaves the stale bytes counted, so the next write
+// starts another one instead of waiting for the threshold to fill up again.
+#[test]
+fn failed_compaction_retried() -> Result<()> {
+    let temp_dir = TempDir::new().expect("unable to create temporary working directory");
+    let policy = CompactionPolicy {
+        stale_bytes: Some(1000),
+        ..CompactionPolicy::manual()
+    };
+    let store = open_with_policy(temp_dir.path(), policy)?;
+    // ! the first compaction writes gen 2 to this temp file
+    fs::create_dir(temp_dir.path().join("2.log.tmp"))?;
+    // ! enough to reach the threshold once, not twice
+    for _ in 0..12 {
+        store.set("key".to_owned(), "v".repeat(100))?;
+    }
+    // ! new keys make nothing stale
+    for i in 0..100 {
+        store.set(format!("key{}", i), format!("value{}", i))?;
+        thread::sleep(Duration::from_millis(5));
+    }
+    drop(store);
+
+    assert!(temp_dir.path().join("MANIFEST").exists());
+    fs::remove_dir(temp_dir.path().join("2.log.tmp"))?;
+    let store = KvStore::open(temp_dir.path())?;
+    assert_eq!(store.get("key".to_owned())?, Some("v".repeat(100)));
+    assert_eq!(store.scan(..).count(), 101);
+    Ok(())
+}
+
+#[test]
+fn stale_ratio_policy() -> Result<()> {
+    let temp_dir = TempDir::new().expect("unable to create temporary working directory");
+    let policy = CompactionPolicy {
+        stale_ratio: Some(0.6),
+        ..CompactionPolicy::manual()
+    };
+    let store = open_with_policy(temp_dir.path(), policy)?;
+    for i in 0..10 {
+        store.set(format!("key{}", i), format!("value{}", i))?;
+    }
+    // half of the records are stale, below the ratio
+    for i in 0..10 {
+        store.set(format!("key{}", i), format!("value{}", i))?;
+    }
+    assert!(!temp_dir.path().join("MANIFEST").exists());
+    for i in 0..5 {
+        store.set(format!("key{}", i), format!("value{}", i))?;
+    }
+    drop(store);
+    assert!(temp_dir.path().join("MANIFEST").exists());
+    Ok(())
+}
+
+// A threshold that would compact on every write is refused before anything is created.
+#[test]
+fn invalid_compaction_policy() {
+    let temp_dir = TempDir::new().expect("unable to create temporary working directory");
+    let ratio = |ratio| CompactionPolicy {
+        stale_ratio: Some(ratio),
+        ..CompactionPolicy::manual()
+    };
+    let invalid = [
+        ratio(0.0),
+        ratio(-0.5),
+        ratio(1.5),
+        ratio(f64::NAN),
+        CompactionPolicy {
+            stale_bytes: Some(0),
+            ..CompactionPolicy::manual()
+        },
+        CompactionPolicy {
+            max_gens: Some(0),
+            ..CompactionPolicy::manual()
+        },
+        CompactionPolicy {
+            max_gens: Some(1),
+            ..CompactionPolicy::manual()
+        },
+    ];
+    for policy in &invalid {
+        assert!(
+            matches!(
+                open_with_policy(&temp_dir.path().join("store"), *policy),
+                Err(KvError::InvalidOption(_))
+            ),
+            "{:?}",
+            policy
+        );
+    }
+    assert!(!temp_dir.path().join("store").exists());
+    let valid = CompactionPolicy {
+        stale_bytes: Some(1),
+        stale_ratio: Some(1.0),
+        max_gens: Some(2),
+    };
+    assert!(open_with_policy(temp_dir.path(), valid).is_ok());
+}
+
+// A write that fills the active generation starts a new one, the one that
+// goes over the limit compacts.
+#[test]
+fn max_gens_policy() -> Result<()> {
+    let temp_dir = TempDir::new().expect("unable to create temporary working directory");
+    let options = Options {
+        compaction: CompactionPolicy {
+            max_gens: Some(3),
+            ..CompactionPolicy::manual()
+        },
+        max_file_size: 1,
+        ..Options::default()
+    };
+    let store = KvStore::open_with_options(temp_dir.path(), options)?;
+    for i in 0..2 {
+        store.set(format!("key{}", i), format!("value{}", i))?;
+    }
+    assert_eq!(logs(temp_dir.path()).len(), 3);
+    assert!(!temp_dir.path().join("MANIFEST").exists());
+
+    store.set("key2".to_owned(), "value2".to_owned())?;
+    drop(store);
+    assert!(temp_dir.path().join("MANIFEST").exists());
+    assert_eq!(logs(temp_dir.path()).len(), 2);
+
+    let store = KvStore::open(temp_dir.path())?;
+    for i in 0..3 {
+        assert_eq!(store.get(format!("key{}", i))?, Some(format!("value{}", i)));
+    }
+    Ok(())
+}
+
+// Opening a store again keeps appending to the last generation.
+#[test]
+fn open_resumes_last_gen() -> Result<()> {
+    let temp_dir = TempDir::new().expect("unable to create temporary working directory");
+    for i in 0..5 {
+        let store = KvStore::open(temp_dir.path())?;
+        store.set(format!("key{}", i), format!("value{}", i))?;
+    }
+    assert_eq!(logs(temp_dir.path()).len(), 1);
+
+    let store = KvStore::open(temp_dir.path())?;
+    store.compaction()?;
+    drop(store);
+    let compacted = logs(temp_dir.path());
+    let store = KvStore::open(temp_dir.path())?;
+    store.set("key5".to_owned(), "value5".to_owned())?;
+    drop(store);
+    assert_eq!(logs(temp_dir.path()).len(), compacted.len());
+
+    let store = KvStore::open(temp_dir.path())?;
+    for i in 0..6 {
+        assert_eq!(store.get(format!("key{}", i))?, Some(format!("value{}", i)));
+    }
+    Ok(())
+}
+
+// Writes roll over to a new generation once the active one reaches
+// `max_file_size`, and a full last generation is not reopened.
+#[test]
+fn max_file_size() -> Result<()> {
+    let temp_dir = TempDir::new().expect("unable to create temporary working directory");
+    let options = Options {
+        compaction: CompactionPolicy::manual(),
+        max_file_size: 1024,
+        ..Options::default()
+    };
+    let value = "v".repeat(100);
+    let store = KvStore::open_with_options(temp_dir.path(), options.clone())?;
+    for i in 0..50 {
+        store.set(format!("key{}", i), value.clone())?;
+    }
+    drop(store);
+    let logs_before = logs(temp_dir.path());
+    assert!(logs_before.len() >= 5);
+    assert!(logs_before
+        .values()
+        .all(|content| content.len() < 1024 + value.len() * 2));
+
+    let store = KvStore::open_with_options(temp_dir.path(), options)?;
+    for i in 0..50 {
+        assert_eq!(store.get(format!("key{}", i))?, Some(value.clone()));
+    }
+    store.set("key".to_owned(), "value".to_owned())?;
+    drop(store);
+    let logs_after = logs(temp_dir.path());
+    assert_eq!(logs_after.len(), logs_before.len());
+    Ok(())
+}
+
+// A new generation that cannot be created keeps writes on the active one,
+// and the write that filled it still succeeds.
+#[test]
+fn failed_roll_over() -> Result<()> {
+    let temp_dir = TempDir::new().expect("unable to create temporary working directory");
+    fs::create_dir(temp_dir.path().join("2.log"))?;
+    let options = Options {
+        compaction: CompactionPolicy::manual(),
+        max_file_size: 64,
+        ..Options::default()
+    };
+    let store = KvStore::open_with_options(temp_dir.path(), options)?;
+    for i in 0..5 {
+        store.set(format!("key{}", i), "v".repeat(100))?;
+    }
+    for i in 0..5 {
+        assert_eq!(store.get(format!("key{}", i))?, Some("v".repeat(100)));
+    }
+
+    fs::remove_dir(temp_dir.path().join("2.log"))?;
+    store.set("key5".to_owned(), "value5".to_owned())?;
+    store.set("key6".to_owned(), "value6".to_owned())?;
+    assert!(temp_dir.path().join("2.log").is_file());
+    drop(store);
+    let store = KvStore::open(temp_dir.path())?;
+    assert_eq!(store.get("key0".to_owned())?, Some("v".repeat(100)));
+    assert_eq!(store.get("key6".to_owned())?, Some("value6".to_owned()));
+    Ok(())
+}
+
+// A `kv` run on a directory that is mostly stale compacts it before exiting.
+#[test]
+fn cli_compacts_stale_directory() -> Result<()> {
+    let temp_dir = TempDir::new().expect("unable to create temporary working directory");
+    let store = open_with_policy(temp_dir.path(), CompactionPolicy::manual())?;
+    let value = "v".repeat(1000);
+    for _ in 0..300 {
+        for i in 0..10 {
+            store.set(format!("key{}", i), value.clone())?;
+        }
+    }
+    drop(store);
+    let stale_size: usize = logs(temp_dir.path()).values().map(Vec::len).sum();
+
+    Command::cargo_bin("kv")
+        .unwrap()
+        .args(["set", "key", "value"])
+        .current_dir(&temp_dir)
+        .assert()
+        .success();
+    let size: usize = logs(temp_dir.path()).values().map(Vec::len).sum();
+    assert!(
+        size < stale_size / 10,
+        "{} of {} bytes left",
+        size,
+        stale_size
+    );
+    assert!(files(temp_dir.path())
+        .iter()
+        .all(|path| path.extension() != Some("tmp".as_ref())));
+
+    let store = KvStore::open(temp_dir.path())?;
+    assert_eq!(store.get("key0".to_owned())?, Some(value));
+    assert_eq!(store.get("key".to_owned())?, Some("value".to_owned()));
+    Ok(())
+}
@@ -0,0 +1,140 @@
+use kv::{KvStore, Result};
+use std::sync::Arc;
+use std::thread;
+use tempfile::TempDir;
+
+fn assert_send_sync<T: Send + Sync + Clone + 'static>() {}
+
+#[test]
+fn store_is_send_sync() {
+    assert_send_sync::<KvStore>();
+}
+
+// Writes from several threads through cloned handles all land in the store.
+#[test]
+fn concurrent_set() -> Result<()> {
+    let temp_dir = TempDir::new().expect("unable to create temporary working directory");
+    let store = KvStore::open(temp_dir.path())?;
+
+    let handles: Vec<_> = (0..8)
+        .map(|t| {
+            let store = store.clone();
+            thread::spawn(move || {
+                for i in 0..100 {
+                    store
+                        .set(format!("key{}_{}", t, i), format!("value{}", i))
+                        .unwrap();
+                }
+            })
+        })
+        .collect();
+    for handle in handles {
+        handle.join().unwrap();
+    }
+
+    for t in 0..8 {
+        for i in 0..100 {
+            assert_eq!(
+                store.get(format!("key{}_{}", t, i))?,
+                Some(format!("value{}", i))
+            );
+        }
+    }
+
+    drop(store);
+    let store = KvStore::open(temp_dir.path())?;
+    assert_eq!(store.scan(..).count(), 800);
+    Ok(())
+}
+
+// Threads bumping a counter with compare-and-set never lose an update.
+#[test]
+fn concurrent_compare_and_set() -> Result<()> {
+    let temp_dir = TempDir::new().expect("unable to create temporary working directory");
+    let store = KvStore::open(temp_dir.path())?;
+    store.set("counter".to_owned(), "0".to_owned())?;
+
+    let handles: Vec<_> = (0..8)
+        .map(|_| {
+            let store = store.clone();
+            thread::spawn(move || {
+                let mut done = 0;
+                while done < 50 {
+                    let current = store.get("counter".to_owned()).unwrap().unwrap();
+                    let next = (current.parse::<u64>().unwrap() + 1).to_string();
+                    if store
+                        .compare_and_set("counter".to_owned(), current, next)
+                        .unwrap()
+                    {
+                        done += 1;
+                    }
+                }
+            })
+        })
+        .collect();
+    for handle in handles {
+        handle.join().unwrap();
+    }
+
+    assert_eq!(store.get("counter".to_owned())?, Some("400".to_owned()));
+    Ok(())
+}
+
+// Increments from several threads all count.
+#[test]
+fn concurrent_incr() -> Result<()> {
+    let temp_dir = TempDir::new().expect("unable to create temporary working directory");
+    let store = KvStore::open(temp_dir.path())?;
+
+    let handles: Vec<_> = (0..8)
+        .map(|_| {
+            let store = store.clone();
+            thread::spawn(move || {
+                for _ in 0..50 {
+                    store.incr("counter".to_owned(), 2).unwrap();
+                }
+            })
+        })
+        .collect();
+    for handle in handles {
+        handle.join().unwrap();
+    }
+
+    assert_eq!(store.incr("counter".to_owned(), 0)?, 800);
+    Ok(())
+}
+
+// Readers sharing one handle keep seeing correct values while compactions
+// move every key to new generations and delete the old log files.
+#[test]
+fn concurrent_get_during_compaction() -> Result<()> {
+    let temp_dir = TempDir::new().expect("unable to create temporary working directory");
+    let store = Arc::new(KvStore::open(temp_dir.path())?);
+    for i in 0..100 {
+        store.set(format!("key{}", i), format!("value{}", i))?;
+    }
+
+    let readers: Vec<_> = (0..4)
+        .map(|_| {
+            let store = Arc::clone(&store);
+            thread::spawn(move || {
+                for _ in 0..20 {
+                    for i in 0..100 {
+                        assert_eq!(
+                            store.get(format!("key{}", i)).unwrap(),
+                            Some(format!("value{}", i))
+                        );
+                    }
+                }
+            })
+        })
+        .collect();
+
+    for _ in 0..20 {
+        store.compaction()?;
+    }
+    for reader in readers {
+        reader.join().unwrap();
+    }
+    Ok(())
+}
@@ -0,0 +1,186 @@
+mod common;
+
+use assert_cmd::prelude::*;
+use common::kv;
+use kv::error::KvError;
+use kv::{CompactionPolicy, Config, Durability, EngineKind, Options, Result};
+use predicates::str::{contains, PredicateStrExt};
+use std::fs;
+use std::path::Path;
+use std::time::Duration;
+use tempfile::TempDir;
+
+fn is_empty(dir: &Path) -> bool {
+    fs::read_dir(dir).unwrap().next().is_none()
+}
+
+#[test]
+fn parse_config() -> Result<()> {
+    let config: Config = r#"
+        data_dir = "/var/lib/kv"
+        engine = "kvs"
+        durability = "periodic:100:4096"
+        max_file_size = 1024
+
+        [compaction]
+        stale_ratio = 0.5
+    "#
+    .parse()?;
+    assert_eq!(config.data_dir, Some("/var/lib/kv".into()));
+    assert_eq!(config.engine, Some(EngineKind::Kvs));
+    assert_eq!(
+        config.durability,
+        Some(Durability::Periodic {
+            interval: Duration::from_millis(100),
+            bytes: 4096,
+        })
+    );
+    let options = config.options();
+    assert_eq!(options.max_file_size, 1024);
+    assert_eq!(
+        options.compaction,
+        CompactionPolicy {
+            stale_ratio: Some(0.5),
+            ..CompactionPolicy::manual()
+        }
+    );
+
+    // whatever the file leaves out keeps its default
+    let options = "".parse::<Config>()?.options();
+    assert_eq!(options.durability, Options::default().durability);
+    assert_eq!(options.compaction, CompactionPolicy::default());
+    assert_eq!(options.max_file_size, Options::DEFAULT_MAX_FILE_SIZE);
+    assert!("[compaction]"
+        .parse::<Config>()?
+        .options()
+        .compaction
+        .is_manual());
+
+    for invalid in &[
+        "durability = \"sometimes\"",
+        "engine = \"sled\"",
+        "max_file_size = \"big\"",
+        "unknown = 1",
+        "[compaction]\nmax_size = 1",
+        "[compaction]\nstale_ratio = 0.0",
+        "[compaction]\nstale_ratio = 1.5",
+        "[compaction]\nstale_ratio = nan",
+        "[compaction]\nstale_bytes = 0",
+        "[compaction]\nmax_gens = 0",
+        "[compaction]\nmax_gens = 1",
+    ] {
+        assert!(invalid.parse::<Config>().is_err(), "{}", invalid);
+    }
+    assert!(matches!(
+        "engine = \"sled\"".parse::<Config>(),
+        Err(KvError::UnknownEngine(_))
+    ));
+    assert!(matches!(
+        "[compaction]\nstale_ratio = -0.5".parse::<Config>(),
+        Err(KvError::InvalidOption(_))
+    ));
+    Ok(())
+}
+
+// `--data-dir` and `KV_DATA_DIR` point the CLI at a store elsewhere,
+// the flag wins over the environment.
+#[test]
+fn cli_data_dir() {
+    let cwd = TempDir::new().expect("unable to create temporary working directory");
+    let data = TempDir::new().expect("unable to create temporary working directory");
+    let other = TempDir::new().expect("unable to create temporary working directory");
+
+    kv(cwd.path())
+        .args(["set", "key", "value", "--data-dir"])
+        .arg(data.path())
+        .assert()
+        .success();
+    kv(cwd.path())
+        .env("KV_DATA_DIR", data.path())
+        .args(["get", "key"])
+        .assert()
+        .success()
+        .stdout(contains("value").trim());
+    kv(cwd.path())
+        .env("KV_DATA_DIR", data.path())
+        .args(["get", "key", "--data-dir"])
+        .arg(other.path())
+        .assert()
+        .success()
+        .stdout(contains("Key not found").trim());
+    assert!(is_empty(cwd.path()));
+    assert!(!is_empty(data.path()));
+}
+
+// The config file supplies the data directory, relative to the file, and
+// the engine, flags and environment variables win over it.
+#[test]
+fn cli_config_file() {
+    let cwd = TempDir::new().expect("unable to create temporary working directory");
+    let etc = TempDir::new().expect("unable to create temporary working directory");
+    let other = TempDir::new().expect("unable to create temporary working directory");
+    let config = etc.path().join("kv.toml");
+    fs::write(
+        &config,
+        "data_dir = \"data\"\nengine = \"kvs\"\ndurability = \"always\"\n",
+    )
+    .unwrap();
+
+    kv(cwd.path())
+        .args(["set", "key", "value", "--config"])
+        .arg(&config)
+        .assert()
+        .success();
+    assert!(etc.path().join("data").join("engine").exists());
+    kv(cwd.path())
+        .env("KV_CONFIG", &config)
+        .args(["get", "key"])
+        .assert()
+        .success()
+        .stdout(contains("value").trim());
+    kv(cwd.path())
+        .env("KV_CONFIG", &config)
+        .env("KV_DATA_DIR", other.path())
+        .args(["get", "key"])
+        .assert()
+        .success()
+        .stdout(contains("Key not found").trim());
+    kv(cwd.path())
+        .env("KV_CONFIG", &config)
+        .args(["get", "key", "--engine", "memory"])
+        .assert()
+        .failure()
+        .stderr(contains("Wrong engine"));
+    assert!(is_empty(cwd.path()));
+
+    fs::write(&config, "durability = \"sometimes\"\n").unwrap();
+    kv(cwd.path())
+        .args(["get", "key", "--config"])
+        .arg(&config)
+        .assert()
+        .failure()
+        .stderr(contains("durability sometimes"));
+    kv(cwd.path())
+        .args(["get", "key", "--config"])
+        .arg(etc.path().join("missing.toml"))
+        .assert()
+        .failure()
+        .stderr(contains("config file"));
+}
+
+// A data directory that cannot be used is named in the error.
+#[test]
+fn cli_unusable_data_dir() {
+    let cwd = TempDir::new().expect("unable to create temporary working directory");
+    let file = cwd.path().join("file");
+    fs::write(&file, "not a directory").unwrap();
+    for cmd in [&["get", "key"][..], &["set", "key", "value"][..]] {
+        kv(cwd.path())
+            .args(cmd)
+            .arg("--data-dir")
+            .arg(&file)
+            .assert()
+            .code(22)
+            .stderr(contains(file.to_string_lossy()));
+    }
+}
@@ -0,0 +1,418 @@
+use assert_cmd::prelude::*;
+use kv::error::KvError;
+use kv::{Durability, KvStore, KvsEngine, MemStore, Options, Result};
+use predicates::str::contains;
+use std::process::Command;
+use std::thread;
+use std::time::Duration;
+use tempfile::TempDir;
+
+fn check_basic_ops<E: KvsEngine>(engine: E) -> Result<()> {
+    engine.set("key1".to_owned(), "value1".to_owned())?;
+    engine.set("key2".to_owned(), "value2".to_owned())?;
+    engine.set("key1".to_owned(), "value3".to_owned())?;
+    assert_eq!(engine.get("key1".to_owned())?, Some("value3".to_owned()));
+    assert_eq!(engine.get("key3".to_owned())?, None);
+
+    engine.remove("key2".to_owned())?;
+    assert_eq!(engine.get("key2".to_owned())?, None);
+    assert!(engine.remove("key2".to_owned()).is_err());
+    Ok(())
+}
+
+fn check_scan<E: KvsEngine>(engine: E) -> Result<()> {
+    for i in 0..5 {
+        engine.set(format!("key{}", i), format!("value{}", i))?;
+    }
+    engine.remove("key2".to_owned())?;
+
+    let pairs = engine
+        .scan("key1".to_owned().."key4".to_owned())
+        .collect::<Result<Vec<_>>>()?;
+    assert_eq!(
+        pairs,
+        vec![
+            ("key1".to_owned(), "value1".to_owned()),
+            ("key3".to_owned(), "value3".to_owned()),
+        ]
+    );
+    assert_eq!(engine.scan(..).count(), 4);
+
+    let keys = |pairs: Vec<(String, String)>| -> Vec<String> {
+        pairs.into_iter().map(|(key, _)| key).collect()
+    };
+    let reversed = engine.scan(..).rev().collect::<Result<Vec<_>>>()?;
+    assert_eq!(keys(reversed), vec!["key4", "key3", "key1", "key0"]);
+    let limited = engine.scan(..).rev().take(2).collect::<Result<Vec<_>>>()?;
+    assert_eq!(keys(limited), vec!["key4", "key3"]);
+
+    // both ends of one scan meet in the middle without repeating a key
+    let mut scan = engine.scan(..);
+    let mut seen = vec![];
+    while let (Some(front), back) = (scan.next(), scan.next_back()) {
+        seen.push(front?.0);
+        if let Some(back) = back {
+            seen.push(back?.0);
+        }
+    }
+    seen.sort();
+    assert_eq!(seen, vec!["key0", "key1", "key3", "key4"]);
+
+    engine.set("kex".to_owned(), "value".to_owned())?;
+    engine.set("kez".to_owned(), "value".to_owned())?;
+    let prefixed = engine.scan_prefix("key").collect::<Result<Vec<_>>>()?;
+    assert_eq!(keys(prefixed), vec!["key0", "key1", "key3", "key4"]);
+    assert_eq!(engine.scan_prefix("").count(), 6);
+
+    engine.set_bytes(&[0xff, 0xff], b"value")?;
+    engine.set_bytes(&[0xff, 0xff, 0x01], b"value")?;
+    engine.set_bytes(&[0xff, 0xfe], b"value")?;
+    assert_eq!(engine.scan_prefix_bytes(&[0xff, 0xff]).count(), 2);
+    Ok(())
+}
+
+fn check_bytes<E: KvsEngine>(engine: E) -> Result<()> {
+    let key = [0xff, 0x00, b'k'];
+    let value = [0x00, 0x9f, 0x92, 0x96, 0xc3];
+    engine.set_bytes(&key, &value)?;
+    engine.set_bytes(b"empty", b"")?;
+    assert_eq!(engine.get_bytes(&key)?, Some(value.to_vec()));
+    assert_eq!(engine.get_bytes(b"empty")?, Some(vec![]));
+    assert_eq!(engine.get("empty".to_owned())?, Some(String::new()));
+    engine.set("text".to_owned(), "value".to_owned())?;
+    assert!(matches!(
+        engine
+            .scan_bytes(vec![0xff]..)
+            .collect::<Result<Vec<_>>>()?
+            .as_slice(),
+        [(k, v)] if k == &key && v == &value
+    ));
+
+    engine.set_bytes(b"binary", &value)?;
+    assert!(matches!(
+        engine.get("binary".to_owned()),
+        Err(KvError::NotUtf8)
+    ));
+    engine.remove_bytes(&key)?;
+    assert_eq!(engine.get_bytes(&key)?, None);
+    Ok(())
+}
+
+fn check_conditional<E: KvsEngine>(engine: E) -> Result<()> {
+    assert!(engine.set_if_absent("key".to_owned(), "v1".to_owned())?);
+    assert!(!engine.set_if_absent("key".to_owned(), "v2".to_owned())?);
+    assert_eq!(engine.get("key".to_owned())?, Some("v1".to_owned()));
+
+    assert!(!engine.compare_and_set("key".to_owned(), "v2".to_owned(), "v3".to_owned())?);
+    assert!(engine.compare_and_set("key".to_owned(), "v1".to_owned(), "v3".to_owned())?);
+    assert_eq!(engine.get("key".to_owned())?, Some("v3".to_owned()));
+    assert!(!engine.compare_and_set("missing".to_owned(), "".to_owned(), "v".to_owned())?);
+    assert_eq!(engine.get("missing".to_owned())?, None);
+
+    assert!(!engine.remove_if_equals("key".to_owned(), "v1".to_owned())?);
+    assert!(engine.remove_if_equals("key".to_owned(), "v3".to_owned())?);
+    assert_eq!(engine.get("key".to_owned())?, None);
+    assert!(!engine.remove_if_equals("key".to_owned(), "v3".to_owned())?);
+
+    assert!(engine.compare_and_swap_bytes(b"bytes", None, None)?);
+    assert!(engine.compare_and_swap_bytes(b"bytes", None, Some(&[0xff]))?);
+    assert!(!engine.compare_and_swap_bytes(b"bytes", None, None)?);
+    assert!(engine.compare_and_swap_bytes(b"bytes", Some(&[0xff]), None)?);
+    assert_eq!(engine.get_bytes(b"bytes")?, None);
+    Ok(())
+}
+
+fn check_counters<E: KvsEngine>(engine: E) -> Result<()> {
+    assert_eq!(engine.incr("hits".to_owned(), 1)?, 1);
+    assert_eq!(engine.incr("hits".to_owned(), 41)?, 42);
+    assert_eq!(engine.decr("hits".to_owned(), 50)?, -8);
+    assert_eq!(engine.get("hits".to_owned())?, Some("-8".to_owned()));
+    engine.set("text".to_owned(), "ten".to_owned())?;
+    assert!(matches!(
+        engine.incr("text".to_owned(), 1),
+        Err(KvError::NotAnInteger(ref key)) if key == "text"
+    ));
+    engine.set("max".to_owned(), i64::MAX.to_string())?;
+    assert!(matches!(
+        engine.incr("max".to_owned(), 1),
+        Err(KvError::Overflow(_))
+    ));
+    assert!(matches!(
+        engine.decr("hits".to_owned(), i64::MIN),
+        Err(KvError::Overflow(_))
+    ));
+    assert_eq!(engine.get("max".to_owned())?, Some(i64::MAX.to_string()));
+    Ok(())
+}
+
+#[test]
+fn kvs_engine_basic_ops() -> Result<()> {
+    let temp_dir = TempDir::new().expect("unable to create temporary working directory");
+    check_basic_ops(KvStore::open(temp_dir.path())?)
+}
+
+#[test]
+fn memory_engine_basic_ops() -> Result<()> {
+    check_basic_ops(MemStore::new())
+}
+
+#[test]
+fn kvs_engine_scan() -> Result<()> {
+    let temp_dir = TempDir::new().expect("unable to create temporary working directory");
+    check_scan(KvStore::open(temp_dir.path())?)
+}
+
+#[test]
+fn memory_engine_scan() -> Result<()> {
+    check_scan(MemStore::new())
+}
+
+#[test]
+fn kvs_engine_bytes() -> Result<()> {
+    let temp_dir = TempDir::new().expect("unable to create temporary working directory");
+    check_bytes(KvStore::open(temp_dir.path())?)
+}
+
+#[test]
+fn memory_engine_bytes() -> Result<()> {
+    check_bytes(MemStore::new())
+}
+
+#[test]
+fn kvs_engine_conditional() -> Result<()> {
+    let temp_dir = TempDir::new().expect("unable to create temporary working directory");
+    check_conditional(KvStore::open(temp_dir.path())?)
+}
+
+#[test]
+fn memory_engine_conditional() -> Result<()> {
+    check_conditional(MemStore::new())
+}
+
+#[test]
+fn kvs_engine_counters() -> Result<()> {
+    let temp_dir = TempDir::new().expect("unable to create temporary working directory");
+    check_counters(KvStore::open(temp_dir.path())?)
+}
+
+#[test]
+fn memory_engine_counters() -> Result<()> {
+    check_counters(MemStore::new())
+}
+
+// Arbitrary bytes survive both replaying the log and compaction.
+#[test]
+fn bytes_persist() -> Result<()> {
+    let temp_dir = TempDir::new().expect("unable to create temporary working directory");
+    let pairs: Vec<(Vec<u8>, Vec<u8>)> = (0..=255u8)
+        .map(|b| (vec![b, 0xfe], vec![0xff, b, 0x00]))
+        .collect();
+    let store = KvStore::open(temp_dir.path())?;
+    for (key, value) in &pairs {
+        store.set_bytes(key, value)?;
+    }
+    drop(store);
+
+    let store = KvStore::open(temp_dir.path())?;
+    assert_eq!(store.scan_bytes(..).collect::<Result<Vec<_>>>()?, pairs);
+    store.compaction()?;
+    drop(store);
+
+    let store = KvStore::open(temp_dir.path())?;
+    assert_eq!(store.scan_bytes(..).collect::<Result<Vec<_>>>()?, pairs);
+    Ok(())
+}
+
+// A directory written by one engine must not be opened with another.
+#[test]
+fn cli_wrong_engine() {
+    let temp_dir = TempDir::new().expect("unable to create temporary working directory");
+    Command::cargo_bin("kv")
+        .unwrap()
+        .args(["--engine", "kvs", "set", "key1", "value1"])
+        .current_dir(&temp_dir)
+        .assert()
+        .success();
+
+    Command::cargo_bin("kv")
+        .unwrap()
+        .args(["--engine", "memory", "get", "key1"])
+        .current_dir(&temp_dir)
+        .assert()
+        .failure()
+        .stderr(contains("Wrong engine"));
+
+    Command::cargo_bin("kv")
+        .unwrap()
+        .args(["get", "key1"])
+        .current_dir(&temp_dir)
+        .assert()
+        .success()
+        .stdout(contains("value1"));
+}
+
+// A directory with logs but no engine file was written by kvs.
+#[test]
+fn cli_unmarked_kvs_directory() {
+    let temp_dir = TempDir::new().expect("unable to create temporary working directory");
+    Command::cargo_bin("kv")
+        .unwrap()
+        .args(["set", "key1", "value1"])
+        .current_dir(&temp_dir)
+        .assert()
+        .success();
+    std::fs::remove_file(temp_dir.path().join("engine")).unwrap();
+
+    let writes: &[&str] = &["--engine", "memory", "set", "key2", "value2"];
+    let reads: &[&str] = &["--engine", "memory", "get", "key1"];
+    for args in [writes, reads] {
+        Command::cargo_bin("kv")
+            .unwrap()
+            .args(args)
+            .current_dir(&temp_dir)
+            .assert()
+            .failure()
+            .stderr(contains("Wrong engine"));
+    }
+    assert!(!temp_dir.path().join("engine").exists());
+
+    Command::cargo_bin("kv")
+        .unwrap()
+        .args(["get", "key1"])
+        .current_dir(&temp_dir)
+        .assert()
+        .success()
+        .stdout(contains("value1"));
+    Command::cargo_bin("kv")
+        .unwrap()
+        .args(["set", "key2", "value2"])
+        .current_dir(&temp_dir)
+        .assert()
+        .success();
+    assert_eq!(
+        std::fs::read_to_string(temp_dir.path().join("engine")).unwrap(),
+        "kvs"
+    );
+}
+
+#[test]
+fn cli_invalid_engine() {
+    let temp_dir = TempDir::new().expect("unable to create temporary working directory");
+    Command::cargo_bin("kv")
+        .unwrap()
+        .args(["--engine", "sled", "get", "key1"])
+        .current_dir(&temp_dir)
+        .assert()
+        .failure();
+}
+
+#[test]
+fn durability_modes() -> Result<()> {
+    let modes = vec![
+        Durability::Always,
+        Durability::Never,
+        Durability::Periodic {
+            interval: Duration::from_millis(10),
+            bytes: 64,
+        },
+    ];
+    for durability in modes {
+        let temp_dir = TempDir::new().expect("unable to create temporary working directory");
+        let store = KvStore::open_with_options(
+            temp_dir.path(),
+            Options {
+                durability,
+                ..Options::default()
+            },
+        )?;
+        for i in 0..20 {
+            store.set(format!("key{}", i), format!("value{}", i))?;
+        }
+        store.remove("key0".to_owned())?;
+        thread::sleep(Duration::from_millis(30));
+        drop(store);
+
+        let store = KvStore::open(temp_dir.path())?;
+        assert_eq!(store.get("key0".to_owned())?, None);
+        assert_eq!(store.get("key19".to_owned())?, Some("value19".to_owned()));
+    }
+
+    // a zero interval would keep the syncer busy
+    let temp_dir = TempDir::new().expect("unable to create temporary working directory");
+    let options = Options {
+        durability: Durability::Periodic {
+            interval: Duration::ZERO,
+            bytes: 64,
+        },
+        ..Options::default()
+    };
+    assert!(matches!(
+        KvStore::open_with_options(temp_dir.path(), options),
+        Err(KvError::InvalidOption(_))
+    ));
+    Ok(())
+}
+
+#[test]
+fn parse_durability() {
+    assert_eq!("always".parse::<Durability>().unwrap(), Durability::Always);
+    assert_eq!("never".parse::<Durability>().unwrap(), Durability::Never);
+    assert_eq!(
+        "periodic:50:4096".parse::<Durability>().unwrap(),
+        Durability::Periodic {
+            interval: Duration::from_millis(50),
+            bytes: 4096,
+        }
+    );
+    assert!("periodic:50".parse::<Durability>().is_err());
+    assert!("periodic:0:4096".parse::<Durability>().is_err());
+    assert!("sometimes".parse::<Durability>().is_err());
+}
+
+#[test]
+fn cli_durability() {
+    let temp_dir = TempDir::new().expect("unable to create temporary working directory");
+    Command::cargo_bin("kv")
+        .unwrap()
+        .args(["--durability", "always", "set", "key1", "value1"])
+        .current_dir(&temp_dir)
+        .assert()
+        .success();
+
+    Command::cargo_bin("kv")
+        .unwrap()
+        .args(["get", "key1", "--durability", "periodic:10:100"])
+        .current_dir(&temp_dir)
+        .assert()
+        .success()
+        .stdout(contains("value1"));
+
+    Command::cargo_bin("kv")
+        .unwrap()
+        .args(["--durability", "sometimes", "get", "key1"])
+        .current_dir(&temp_dir)
+        .assert()
+        .failure();
+}
+
+fn check_error_context<E: KvsEngine>(engine: E) -> Result<()> {
+    let err = engine.remove("missing".to_owned()).unwrap_err();
+    assert!(matches!(&err, KvError::KeyNotFound(key) if key == "missing"));
+    assert_eq!(err.to_string(), "Key not found: missing");
+    assert!(matches!(
+        engine.remove_bytes(&[b'k', 0xff]),
+        Err(KvError::KeyNotFound(key)) if key == "k\u{fffd}"
+    ));
+    Ok(())
+}
+
+#[test]
+fn kvs_engine_error_context() -> Result<()> {
+    let temp_dir = TempDir::new().expect("unable to create temporary working directory");
+    check_error_context(KvStore::open(temp_dir.path())?)
+}
+
+#[test]
+fn memory_engine_error_context() -> Result<()> {
+    check_error_context(MemStore::new())
+}
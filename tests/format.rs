@@ -0,0 +1,305 @@
+mod common;
+
+use assert_cmd::prelude::*;
+use common::{files_with_extension, log_bytes, log_files};
+use kv::error::KvError;
+use kv::{KvStore, Options, Result};
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+use tempfile::TempDir;
+
+// The only log file in a freshly written directory.
+fn single_log(dir: &Path) -> PathBuf {
+    let logs = log_files(dir);
+    assert_eq!(logs.len(), 1);
+    logs.into_iter().next().unwrap()
+}
+
+fn flip_byte(path: &Path, offset: usize) {
+    let mut content = fs::read(path).unwrap();
+    content[offset] ^= 0xff;
+    fs::write(path, content).unwrap();
+}
+
+// Logs written by the JSON encoding still open, and are rewritten in the binary format.
+#[test]
+fn open_json_logs() -> Result<()> {
+    let temp_dir = TempDir::new().expect("unable to create temporary working directory");
+    fs::write(
+        temp_dir.path().join("1.log"),
+        r#"{"Set":{"key":"key1","value":"value1"}}{"Set":{"key":"key2","value":"value2"}}"#,
+    )?;
+    fs::write(
+        temp_dir.path().join("2.log"),
+        r#"{"Remove":{"key":"key2"}}{"Set":{"key":"key1","value":"value3"}}"#,
+    )?;
+    // the JSON engine left an empty log behind on every open
+    fs::write(temp_dir.path().join("3.log"), "")?;
+
+    let store = KvStore::open(temp_dir.path())?;
+    assert_eq!(store.get("key1".to_owned())?, Some("value3".to_owned()));
+    assert_eq!(store.get("key2".to_owned())?, None);
+    drop(store);
+
+    for path in log_files(temp_dir.path()) {
+        let content = fs::read(&path)?;
+        assert!(content.starts_with(b"KVLG"), "{:?} not upgraded", path);
+    }
+
+    let store = KvStore::open(temp_dir.path())?;
+    assert_eq!(store.get("key1".to_owned())?, Some("value3".to_owned()));
+    assert_eq!(store.get("key2".to_owned())?, None);
+    Ok(())
+}
+
+// Keys and values are stored as raw bytes behind a fixed-size header.
+#[test]
+fn binary_record_size() -> Result<()> {
+    let temp_dir = TempDir::new().expect("unable to create temporary working directory");
+    let store = KvStore::open(temp_dir.path())?;
+    store.set("key1".to_owned(), "value1".to_owned())?;
+    drop(store);
+
+    // file header + checksum + record header + "key1" + "value1"
+    assert_eq!(log_bytes(temp_dir.path()), 5 + 4 + 9 + 4 + 6);
+    Ok(())
+}
+
+// A binary log of another format version is refused instead of misread.
+#[test]
+fn unknown_format_version() -> Result<()> {
+    let temp_dir = TempDir::new().expect("unable to create temporary working directory");
+    let store = KvStore::open(temp_dir.path())?;
+    store.set("key1".to_owned(), "value1".to_owned())?;
+    drop(store);
+
+    // the version byte follows the 4 byte magic
+    flip_byte(&single_log(temp_dir.path()), 4);
+    assert!(matches!(
+        KvStore::open(temp_dir.path()),
+        Err(KvError::Corrupted { offset: 4, .. })
+    ));
+    Ok(())
+}
+
+// A flipped bit in a value is caught by `get` and reported with its location by `open`.
+#[test]
+fn detect_corrupted_record() -> Result<()> {
+    let temp_dir = TempDir::new().expect("unable to create temporary working directory");
+    let store = KvStore::open(temp_dir.path())?;
+    store.set("key1".to_owned(), "value1".to_owned())?;
+    store.set("key2".to_owned(), "value2".to_owned())?;
+
+    // the first record starts right after the 5 byte file header
+    let log = single_log(temp_dir.path());
+    flip_byte(&log, 5 + 4 + 9 + 4 + 2);
+
+    assert_eq!(store.get("key2".to_owned())?, Some("value2".to_owned()));
+    match store.get("key1".to_owned()) {
+        Err(KvError::Corrupted { offset: 5, .. }) => {}
+        other => panic!("expected corruption at offset 5, got {:?}", other),
+    }
+    drop(store);
+
+    let gen: u64 = log.file_stem().unwrap().to_str().unwrap().parse().unwrap();
+    match KvStore::open(temp_dir.path()) {
+        Err(KvError::Corrupted { gen: g, offset: 5 }) if g == gen => {}
+        Err(e) => panic!(
+            "expected corruption in gen {} at offset 5, got {:?}",
+            gen, e
+        ),
+        Ok(_) => panic!("expected corruption in gen {} at offset 5", gen),
+    }
+    Ok(())
+}
+
+// A record cut short at the end of the newest log is dropped on open.
+#[test]
+fn recover_torn_tail() -> Result<()> {
+    let temp_dir = TempDir::new().expect("unable to create temporary working directory");
+    let store = KvStore::open(temp_dir.path())?;
+    store.set("key1".to_owned(), "value1".to_owned())?;
+    store.set("key2".to_owned(), "value2".to_owned())?;
+    drop(store);
+
+    let log = single_log(temp_dir.path());
+    let content = fs::read(&log)?;
+    fs::write(&log, &content[..content.len() - 3])?;
+
+    let store = KvStore::open(temp_dir.path())?;
+    assert_eq!(store.get("key1".to_owned())?, Some("value1".to_owned()));
+    assert_eq!(store.get("key2".to_owned())?, None);
+    assert_eq!(fs::metadata(&log)?.len(), 5 + 23);
+
+    // the store keeps working after the truncated tail
+    store.set("key2".to_owned(), "value3".to_owned())?;
+    drop(store);
+    let store = KvStore::open(temp_dir.path())?;
+    assert_eq!(store.get("key2".to_owned())?, Some("value3".to_owned()));
+    Ok(())
+}
+
+// A torn JSON record left by the old encoding is dropped the same way.
+#[test]
+fn recover_torn_json_tail() -> Result<()> {
+    let temp_dir = TempDir::new().expect("unable to create temporary working directory");
+    fs::write(
+        temp_dir.path().join("1.log"),
+        r#"{"Set":{"key":"key1","value":"value1"}}{"Set":{"key":"key2","va"#,
+    )?;
+
+    let store = KvStore::open(temp_dir.path())?;
+    assert_eq!(store.get("key1".to_owned())?, Some("value1".to_owned()));
+    assert_eq!(store.get("key2".to_owned())?, None);
+    Ok(())
+}
+
+// Only the newest generation may end in a torn record.
+#[test]
+fn torn_record_in_older_generation() -> Result<()> {
+    let temp_dir = TempDir::new().expect("unable to create temporary working directory");
+    let store = KvStore::open(temp_dir.path())?;
+    store.set("key1".to_owned(), "value1".to_owned())?;
+    drop(store);
+
+    let log = single_log(temp_dir.path());
+    let content = fs::read(&log)?;
+    fs::write(&log, &content[..content.len() - 3])?;
+
+    // a full last generation is not appended to, key2 goes to a new one
+    let store = KvStore::open_with_options(
+        temp_dir.path(),
+        Options {
+            max_file_size: 1,
+            ..Options::default()
+        },
+    )?;
+    store.set("key2".to_owned(), "value2".to_owned())?;
+    drop(store);
+
+    // the first open fixed the file, now break it again below a newer generation
+    fs::write(&log, &content[..content.len() - 3])?;
+    assert!(matches!(
+        KvStore::open(temp_dir.path()),
+        Err(KvError::Corrupted { .. })
+    ));
+    Ok(())
+}
+
+// Compaction leaves a hint file that open reads instead of replaying the log.
+#[test]
+fn open_from_hint_file() -> Result<()> {
+    let temp_dir = TempDir::new().expect("unable to create temporary working directory");
+    let store = KvStore::open(temp_dir.path())?;
+    for i in 0..10 {
+        store.set(format!("key{}", i), format!("value{}", i))?;
+    }
+    store.compaction()?;
+    store.set("key0".to_owned(), "value10".to_owned())?;
+    drop(store);
+
+    let hints = files_with_extension(temp_dir.path(), "hint");
+    assert_eq!(hints.len(), 1);
+    let compacted = hints[0].with_extension("log");
+
+    // damage the value of key1 in the compacted log: replaying it would fail the open,
+    // going through the hint only fails reading that key
+    flip_byte(&compacted, 5 + 23 + 4 + 9 + 4 + 2);
+    let store = KvStore::open(temp_dir.path())?;
+    assert_eq!(store.get("key0".to_owned())?, Some("value10".to_owned()));
+    assert_eq!(store.get("key9".to_owned())?, Some("value9".to_owned()));
+    assert!(store.get("key1".to_owned()).is_err());
+    drop(store);
+
+    // a hint that does not match its log is ignored
+    flip_byte(&compacted, 5 + 23 + 4 + 9 + 4 + 2);
+    fs::write(&hints[0], b"garbage")?;
+    let store = KvStore::open(temp_dir.path())?;
+    for i in 1..10 {
+        assert_eq!(store.get(format!("key{}", i))?, Some(format!("value{}", i)));
+    }
+    assert_eq!(store.get("key0".to_owned())?, Some("value10".to_owned()));
+    Ok(())
+}
+
+// Hint files of compacted-away generations are removed along with their logs.
+#[test]
+fn stale_hint_files_removed() -> Result<()> {
+    let temp_dir = TempDir::new().expect("unable to create temporary working directory");
+    let store = KvStore::open(temp_dir.path())?;
+    store.set("key1".to_owned(), "value1".to_owned())?;
+    store.compaction()?;
+    store.compaction()?;
+    assert_eq!(files_with_extension(temp_dir.path(), "hint").len(), 1);
+    Ok(())
+}
+
+// A record that can no longer be read is reported with its generation and offset.
+#[test]
+fn read_error_names_record() -> Result<()> {
+    let temp_dir = TempDir::new().expect("unable to create temporary working directory");
+    let store = KvStore::open(temp_dir.path())?;
+    store.set("key1".to_owned(), "value1".to_owned())?;
+    store.set("key2".to_owned(), "value2".to_owned())?;
+    let log = single_log(temp_dir.path());
+    let len = fs::metadata(&log)?.len();
+    fs::OpenOptions::new()
+        .write(true)
+        .open(&log)?
+        .set_len(len - 1)?;
+
+    assert_eq!(store.get("key1".to_owned())?, Some("value1".to_owned()));
+    let err = store.get("key2".to_owned()).unwrap_err();
+    match &err {
+        KvError::Log { offset, source, .. } => {
+            assert!(*offset > 0 && *offset < len - 1);
+            assert_eq!(source.kind(), std::io::ErrorKind::UnexpectedEof);
+        }
+        other => panic!("expected a read error, got {:?}", other),
+    }
+    assert!(err.to_string().contains("generation"));
+    assert!(std::error::Error::source(&err).is_some());
+    Ok(())
+}
+
+// I/O errors while opening a store name the file they happened on.
+#[test]
+fn open_error_names_path() -> Result<()> {
+    let temp_dir = TempDir::new().expect("unable to create temporary working directory");
+    let file = temp_dir.path().join("file");
+    fs::write(&file, "not a directory")?;
+
+    for res in [KvStore::open(&file), KvStore::open_read_only(&file)] {
+        let err = res.err().expect("opened a store in a file");
+        match &err {
+            KvError::File { path, .. } => assert!(path.starts_with(&*file.to_string_lossy())),
+            other => panic!("expected an error on a file, got {:?}", other),
+        }
+        assert!(err.to_string().contains(&*file.to_string_lossy()));
+        assert!(std::error::Error::source(&err).is_some());
+    }
+    Ok(())
+}
+
+// The CLI warns about the torn record it cuts off.
+#[test]
+fn cli_warns_on_torn_record() -> Result<()> {
+    let temp_dir = TempDir::new().expect("unable to create temporary working directory");
+    let store = KvStore::open(temp_dir.path())?;
+    store.set("key1".to_owned(), "value1".to_owned())?;
+    drop(store);
+    let log = single_log(temp_dir.path());
+    let content = fs::read(&log)?;
+    fs::write(&log, &content[..content.len() - 3])?;
+
+    Command::cargo_bin("kv")
+        .unwrap()
+        .args(["set", "key2", "value2"])
+        .current_dir(&temp_dir)
+        .env_remove("RUST_LOG")
+        .assert()
+        .success()
+        .stderr(predicates::str::contains("Truncating torn record"));
+    Ok(())
+}
@@ -0,0 +1,170 @@
+mod common;
+
+use common::log_bytes;
+use kv::error::KvError;
+use kv::{KvStore, Result, WriteBatch};
+use tempfile::TempDir;
+
+fn keys(store: &KvStore) -> Result<Vec<String>> {
+    store
+        .scan(..)
+        .map(|pair| pair.map(|(key, _)| key))
+        .collect()
+}
+
+// Equal keys in different keyspaces do not see each other.
+#[test]
+fn keyspaces_are_separate() -> Result<()> {
+    let temp_dir = TempDir::new().expect("unable to create temporary working directory");
+    let store = KvStore::open(temp_dir.path())?;
+    let users = store.create_keyspace("users")?;
+    let sessions = store.create_keyspace("sessions")?;
+
+    store.set("id".to_owned(), "default".to_owned())?;
+    users.set("id".to_owned(), "alice".to_owned())?;
+    users.set("name".to_owned(), "Alice".to_owned())?;
+    sessions.set("id".to_owned(), "s1".to_owned())?;
+
+    assert_eq!(store.get("id".to_owned())?, Some("default".to_owned()));
+    assert_eq!(users.get("id".to_owned())?, Some("alice".to_owned()));
+    assert_eq!(sessions.get("id".to_owned())?, Some("s1".to_owned()));
+    assert_eq!(store.get("name".to_owned())?, None);
+    assert_eq!(keys(&users)?, vec!["id", "name"]);
+    assert_eq!(keys(&sessions)?, vec!["id"]);
+
+    let mut batch = WriteBatch::new();
+    batch.remove("id".to_owned());
+    batch.set("token".to_owned(), "t1".to_owned());
+    sessions.write(batch)?;
+    assert_eq!(keys(&sessions)?, vec!["token"]);
+    assert_eq!(store.get("id".to_owned())?, Some("default".to_owned()));
+
+    assert_eq!(store.keyspaces(), vec!["sessions", "users"]);
+    assert_eq!(
+        store.keyspace("users")?.get("name".to_owned())?,
+        Some("Alice".to_owned())
+    );
+    assert_eq!(
+        users.keyspace("default")?.get("id".to_owned())?,
+        Some("default".to_owned())
+    );
+    assert!(matches!(
+        store.keyspace("cache"),
+        Err(KvError::KeyspaceNotFound(_))
+    ));
+    assert!(matches!(
+        store.create_keyspace("users"),
+        Err(KvError::KeyspaceExists(_))
+    ));
+    assert!(matches!(
+        store.create_keyspace("default"),
+        Err(KvError::InvalidKeyspace(_))
+    ));
+    assert!(matches!(
+        store.create_keyspace(""),
+        Err(KvError::InvalidKeyspace(_))
+    ));
+    Ok(())
+}
+
+// Keyspaces and their keys come back after replaying the logs and after
+// loading a compacted generation from its hint file.
+#[test]
+fn keyspaces_persist() -> Result<()> {
+    let temp_dir = TempDir::new().expect("unable to create temporary working directory");
+    let store = KvStore::open(temp_dir.path())?;
+    let users = store.create_keyspace("users")?;
+    store.set("key".to_owned(), "default".to_owned())?;
+    users.set("key".to_owned(), "users".to_owned())?;
+    users.set_bytes(&[0xff], &[0x00])?;
+    drop((store, users));
+
+    for _ in 0..2 {
+        let store = KvStore::open(temp_dir.path())?;
+        let users = store.keyspace("users")?;
+        assert_eq!(store.get("key".to_owned())?, Some("default".to_owned()));
+        assert_eq!(users.get("key".to_owned())?, Some("users".to_owned()));
+        assert_eq!(users.get_bytes(&[0xff])?, Some(vec![0x00]));
+        assert_eq!(store.scan_bytes(..).count(), 1);
+        store.compaction()?;
+    }
+    Ok(())
+}
+
+// Dropping a keyspace removes all its keys at once, for good.
+#[test]
+fn drop_keyspace() -> Result<()> {
+    let temp_dir = TempDir::new().expect("unable to create temporary working directory");
+    let store = KvStore::open(temp_dir.path())?;
+    store.set("kept".to_owned(), "value".to_owned())?;
+    store.compaction()?;
+    let live = log_bytes(temp_dir.path());
+
+    let cache = store.create_keyspace("cache")?;
+    for i in 0..100 {
+        cache.set(format!("key{}", i), "value".repeat(10))?;
+    }
+    store.drop_keyspace("cache")?;
+    assert!(store.keyspaces().is_empty());
+    assert!(matches!(
+        cache.get("key0".to_owned()),
+        Err(KvError::KeyspaceNotFound(_))
+    ));
+    assert!(matches!(
+        cache.set("key0".to_owned(), "value".to_owned()),
+        Err(KvError::KeyspaceNotFound(_))
+    ));
+    assert!(matches!(
+        store.drop_keyspace("cache"),
+        Err(KvError::KeyspaceNotFound(_))
+    ));
+    assert!(matches!(
+        store.drop_keyspace("default"),
+        Err(KvError::InvalidKeyspace(_))
+    ));
+    drop(cache);
+
+    // a keyspace created again under the same name starts empty
+    let store = {
+        drop(store);
+        KvStore::open(temp_dir.path())?
+    };
+    assert!(store.keyspace("cache").is_err());
+    let cache = store.create_keyspace("cache")?;
+    assert_eq!(cache.scan(..).count(), 0);
+    drop(cache);
+    store.drop_keyspace("cache")?;
+
+    store.compaction()?;
+    assert_eq!(log_bytes(temp_dir.path()), live);
+    drop(store);
+
+    let store = KvStore::open(temp_dir.path())?;
+    assert_eq!(keys(&store)?, vec!["kept"]);
+    assert!(store.keyspaces().is_empty());
+    Ok(())
+}
+
+// Snapshots and transactions of one keyspace ignore writes to another.
+#[test]
+fn snapshots_per_keyspace() -> Result<()> {
+    let temp_dir = TempDir::new().expect("unable to create temporary working directory");
+    let store = KvStore::open(temp_dir.path())?;
+    let users = store.create_keyspace("users")?;
+    store.set("key".to_owned(), "default".to_owned())?;
+    users.set("key".to_owned(), "v1".to_owned())?;
+
+    let snapshot = users.snapshot();
+    let mut txn = users.begin();
+    assert_eq!(txn.get("key".to_owned())?, Some("v1".to_owned()));
+    store.set("key".to_owned(), "changed".to_owned())?;
+    users.set("other".to_owned(), "value".to_owned())?;
+
+    assert_eq!(snapshot.get("key".to_owned())?, Some("v1".to_owned()));
+    assert_eq!(snapshot.scan(..).count(), 1);
+    txn.set("key".to_owned(), "v2".to_owned());
+    txn.commit()?;
+    assert_eq!(users.get("key".to_owned())?, Some("v2".to_owned()));
+    assert_eq!(store.get("key".to_owned())?, Some("changed".to_owned()));
+    Ok(())
+}
@@ -0,0 +1,105 @@
+use assert_cmd::prelude::*;
+use kv::error::KvError;
+use kv::{KvStore, Result};
+use predicates::str::contains;
+use std::process::Command;
+use tempfile::TempDir;
+
+// Only one store at a time can have a directory open for writing.
+#[test]
+fn second_writer_fails() -> Result<()> {
+    let temp_dir = TempDir::new().expect("unable to create temporary working directory");
+    let store = KvStore::open(temp_dir.path())?;
+    store.set("key".to_owned(), "value".to_owned())?;
+    assert!(matches!(
+        KvStore::open(temp_dir.path()),
+        Err(KvError::Locked(_))
+    ));
+    // clones share the lock
+    let clone = store.clone();
+    drop(store);
+    assert!(KvStore::open(temp_dir.path()).is_err());
+    drop(clone);
+
+    let store = KvStore::open(temp_dir.path())?;
+    assert_eq!(store.get("key".to_owned())?, Some("value".to_owned()));
+    Ok(())
+}
+
+// Read-only stores share the lock with each other but not with a writer.
+#[test]
+fn read_only_shares_lock() -> Result<()> {
+    let temp_dir = TempDir::new().expect("unable to create temporary working directory");
+    let store = KvStore::open(temp_dir.path())?;
+    store.set("key".to_owned(), "value".to_owned())?;
+    assert!(matches!(
+        KvStore::open_read_only(temp_dir.path()),
+        Err(KvError::Locked(_))
+    ));
+    drop(store);
+
+    let reader1 = KvStore::open_read_only(temp_dir.path())?;
+    let reader2 = KvStore::open_read_only(temp_dir.path())?;
+    assert_eq!(reader1.get("key".to_owned())?, Some("value".to_owned()));
+    assert_eq!(reader2.get("key".to_owned())?, Some("value".to_owned()));
+    assert!(matches!(
+        KvStore::open(temp_dir.path()),
+        Err(KvError::Locked(_))
+    ));
+    drop((reader1, reader2));
+    KvStore::open(temp_dir.path())?;
+    Ok(())
+}
+
+// Every write through a read-only store fails.
+#[test]
+fn read_only_rejects_writes() -> Result<()> {
+    let temp_dir = TempDir::new().expect("unable to create temporary working directory");
+    let store = KvStore::open(temp_dir.path())?;
+    store.set("key".to_owned(), "value".to_owned())?;
+    drop(store);
+
+    let store = KvStore::open_read_only(temp_dir.path())?;
+    assert!(matches!(
+        store.set("key".to_owned(), "other".to_owned()),
+        Err(KvError::ReadOnly)
+    ));
+    assert!(matches!(
+        store.remove("key".to_owned()),
+        Err(KvError::ReadOnly)
+    ));
+    assert!(matches!(
+        store.set_if_absent("new".to_owned(), "value".to_owned()),
+        Err(KvError::ReadOnly)
+    ));
+    assert!(matches!(store.compaction(), Err(KvError::ReadOnly)));
+    assert!(matches!(
+        store.create_keyspace("users"),
+        Err(KvError::ReadOnly)
+    ));
+    assert_eq!(store.get("key".to_owned())?, Some("value".to_owned()));
+    Ok(())
+}
+
+// The CLI reports a directory another process is writing to.
+#[test]
+fn cli_locked() -> Result<()> {
+    let temp_dir = TempDir::new().expect("unable to create temporary working directory");
+    let store = KvStore::open(temp_dir.path())?;
+    Command::cargo_bin("kv")
+        .unwrap()
+        .args(["set", "key", "value"])
+        .current_dir(&temp_dir)
+        .assert()
+        .code(20)
+        .stderr(contains("in use by another process"));
+    drop(store);
+
+    Command::cargo_bin("kv")
+        .unwrap()
+        .args(["set", "key", "value"])
+        .current_dir(&temp_dir)
+        .assert()
+        .success();
+    Ok(())
+}
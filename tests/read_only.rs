@@ -0,0 +1,183 @@
+mod common;
+
+use assert_cmd::prelude::*;
+use common::{kv, log_files};
+use kv::{CompactionPolicy, KvStore, Options, Result};
+use predicates::str::{contains, is_empty, PredicateStrExt};
+use std::collections::BTreeMap;
+use std::fs::{self, OpenOptions};
+use std::io::Write;
+use std::path::Path;
+use std::process::Command;
+use tempfile::TempDir;
+
+// Names and sizes of the files in `dir`.
+fn files(dir: &Path) -> BTreeMap<String, u64> {
+    fs::read_dir(dir)
+        .unwrap()
+        .map(|entry| entry.unwrap())
+        .map(|entry| {
+            (
+                entry.file_name().into_string().unwrap(),
+                entry.metadata().unwrap().len(),
+            )
+        })
+        .collect()
+}
+
+// Opening and reading a store read-only leaves its directory as it was.
+#[test]
+fn read_only_creates_no_files() -> Result<()> {
+    let temp_dir = TempDir::new().expect("unable to create temporary working directory");
+    KvStore::open_read_only(temp_dir.path())?;
+    assert!(files(temp_dir.path()).is_empty());
+
+    let store = KvStore::open(temp_dir.path())?;
+    store.set("key1".to_owned(), "value1".to_owned())?;
+    store.compaction()?;
+    store.set("key2".to_owned(), "value2".to_owned())?;
+    drop(store);
+    let before = files(temp_dir.path());
+
+    for _ in 0..3 {
+        let store = KvStore::open_read_only(temp_dir.path())?;
+        assert_eq!(store.get("key1".to_owned())?, Some("value1".to_owned()));
+        assert_eq!(store.get("key2".to_owned())?, Some("value2".to_owned()));
+        assert_eq!(store.scan(..).count(), 2);
+    }
+    assert_eq!(files(temp_dir.path()), before);
+    Ok(())
+}
+
+// A directory that does not exist yet reads as an empty store and is not created.
+#[test]
+fn read_only_missing_dir() -> Result<()> {
+    let temp_dir = TempDir::new().expect("unable to create temporary working directory");
+    let missing = temp_dir.path().join("missing");
+    let store = KvStore::open_read_only(&missing)?;
+    assert_eq!(store.get("key".to_owned())?, None);
+    assert_eq!(store.scan(..).count(), 0);
+    assert!(store.keyspaces().is_empty());
+    drop(store);
+    assert!(!missing.exists());
+    Ok(())
+}
+
+// A read-only store skips a torn last record but leaves cutting it off
+// to the next store opened for writing.
+#[test]
+fn read_only_keeps_torn_record() -> Result<()> {
+    let temp_dir = TempDir::new().expect("unable to create temporary working directory");
+    let store = KvStore::open(temp_dir.path())?;
+    store.set("key".to_owned(), "value".to_owned())?;
+    drop(store);
+    let log = log_files(temp_dir.path()).pop().unwrap();
+    OpenOptions::new()
+        .append(true)
+        .open(&log)?
+        .write_all(&[1, 2, 3])?;
+    let torn_len = fs::metadata(&log)?.len();
+
+    let store = KvStore::open_read_only(temp_dir.path())?;
+    assert_eq!(store.get("key".to_owned())?, Some("value".to_owned()));
+    drop(store);
+    assert_eq!(fs::metadata(&log)?.len(), torn_len);
+
+    let store = KvStore::open(temp_dir.path())?;
+    assert_eq!(store.get("key".to_owned())?, Some("value".to_owned()));
+    assert_eq!(fs::metadata(&log)?.len(), torn_len - 3);
+    Ok(())
+}
+
+// `kv get` on a data directory that does not exist yet finds no key and
+// leaves it uncreated.
+#[test]
+fn cli_get_missing_data_dir() {
+    let temp_dir = TempDir::new().expect("unable to create temporary working directory");
+    let missing = temp_dir.path().join("missing");
+    Command::cargo_bin("kv")
+        .unwrap()
+        .args(["get", "key", "--data-dir"])
+        .arg(&missing)
+        .current_dir(&temp_dir)
+        .env_remove("KV_DATA_DIR")
+        .env_remove("KV_CONFIG")
+        .assert()
+        .success()
+        .stdout(contains("Key not found").trim());
+    assert!(!missing.exists());
+}
+
+// `kv get` does not write to the data directory.
+#[test]
+fn cli_get_creates_no_files() {
+    let temp_dir = TempDir::new().expect("unable to create temporary working directory");
+    Command::cargo_bin("kv")
+        .unwrap()
+        .args(["get", "key"])
+        .current_dir(&temp_dir)
+        .assert()
+        .success()
+        .stdout(contains("Key not found").trim());
+    assert!(files(temp_dir.path()).is_empty());
+
+    Command::cargo_bin("kv")
+        .unwrap()
+        .args(["set", "key", "value"])
+        .current_dir(&temp_dir)
+        .assert()
+        .success();
+    let before = files(temp_dir.path());
+    for _ in 0..3 {
+        Command::cargo_bin("kv")
+            .unwrap()
+            .args(["get", "key"])
+            .current_dir(&temp_dir)
+            .assert()
+            .success()
+            .stdout(contains("value").trim());
+    }
+    assert_eq!(files(temp_dir.path()), before);
+}
+
+// `kv scan` and `kv keys` only read too, even from a directory stale enough
+// that opening it for writing would compact it.
+#[test]
+fn cli_scan_creates_no_files() -> Result<()> {
+    let temp_dir = TempDir::new().expect("unable to create temporary working directory");
+    let missing = temp_dir.path().join("missing");
+    for cmd in &["scan", "keys"] {
+        kv(temp_dir.path())
+            .args([cmd, "--data-dir"])
+            .arg(&missing)
+            .assert()
+            .success()
+            .stdout(is_empty());
+    }
+    assert!(!missing.exists());
+
+    let options = Options {
+        compaction: CompactionPolicy::manual(),
+        ..Options::default()
+    };
+    let store = KvStore::open_with_options(temp_dir.path(), options)?;
+    let value = "v".repeat(1000);
+    for _ in 0..2000 {
+        store.set("key".to_owned(), value.clone())?;
+    }
+    drop(store);
+    let before = files(temp_dir.path());
+
+    kv(temp_dir.path())
+        .args(["scan"])
+        .assert()
+        .success()
+        .stdout(format!("key\t{}\n", value));
+    kv(temp_dir.path())
+        .args(["keys"])
+        .assert()
+        .success()
+        .stdout("key\n");
+    assert_eq!(files(temp_dir.path()), before);
+    Ok(())
+}
@@ -0,0 +1,119 @@
+use assert_cmd::prelude::*;
+use kv::error::KvError;
+use kv::{KvStore, KvsClient, Result};
+use predicates::ord::eq;
+use predicates::str::{contains, PredicateStrExt};
+use std::net::TcpListener;
+use std::process::{Child, Command};
+use std::thread;
+use std::time::Duration;
+use tempfile::TempDir;
+
+struct Server {
+    child: Child,
+    addr: String,
+}
+
+impl Drop for Server {
+    fn drop(&mut self) {
+        let _ = self.child.kill();
+        let _ = self.child.wait();
+    }
+}
+
+// Start `kvs-server` in `dir` on a free port and wait until it accepts connections.
+fn start_server(dir: &TempDir) -> Server {
+    let addr = {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        listener.local_addr().unwrap().to_string()
+    };
+    let child = Command::cargo_bin("kvs-server")
+        .unwrap()
+        .args(["--addr", &addr])
+        .current_dir(dir)
+        .spawn()
+        .unwrap();
+    for _ in 0..50 {
+        if KvsClient::connect(&addr).is_ok() {
+            break;
+        }
+        thread::sleep(Duration::from_millis(100));
+    }
+    Server { child, addr }
+}
+
+#[test]
+fn client_set_get_remove() -> Result<()> {
+    let temp_dir = TempDir::new().expect("unable to create temporary working directory");
+    let server = start_server(&temp_dir);
+
+    let mut client = KvsClient::connect(&server.addr)?;
+    client.set("key1".to_owned(), "value1".to_owned())?;
+    assert_eq!(client.get("key1".to_owned())?, Some("value1".to_owned()));
+    assert_eq!(client.get("key2".to_owned())?, None);
+    client.remove("key1".to_owned())?;
+    assert_eq!(client.get("key1".to_owned())?, None);
+
+    // the server error comes back as the matching KvError variant
+    match client.remove("key1".to_owned()) {
+        Err(KvError::KeyNotFound(_)) => {}
+        other => panic!("expected KeyNotFound, got {:?}", other),
+    }
+    Ok(())
+}
+
+#[test]
+fn cli_client_against_server() {
+    let temp_dir = TempDir::new().expect("unable to create temporary working directory");
+    let server = start_server(&temp_dir);
+
+    Command::cargo_bin("kvs-client")
+        .unwrap()
+        .args(["set", "key1", "value1", "--addr", &server.addr])
+        .assert()
+        .success();
+
+    Command::cargo_bin("kvs-client")
+        .unwrap()
+        .args(["get", "key1", "--addr", &server.addr])
+        .assert()
+        .success()
+        .stdout(eq("value1").trim());
+
+    Command::cargo_bin("kvs-client")
+        .unwrap()
+        .args(["rm", "key2", "--addr", &server.addr])
+        .assert()
+        .failure()
+        .stdout(contains("Key not found"));
+}
+
+#[test]
+fn server_persists_to_store() -> Result<()> {
+    let temp_dir = TempDir::new().expect("unable to create temporary working directory");
+    let server = start_server(&temp_dir);
+
+    let mut client = KvsClient::connect(&server.addr)?;
+    client.set("key1".to_owned(), "value1".to_owned())?;
+    drop(client);
+    drop(server);
+
+    let store = KvStore::open(temp_dir.path())?;
+    assert_eq!(store.get("key1".to_owned())?, Some("value1".to_owned()));
+    Ok(())
+}
+
+// kvs-server exits with the code of the error it failed with.
+#[test]
+fn server_exit_code() -> Result<()> {
+    let temp_dir = TempDir::new().expect("unable to create temporary working directory");
+    let store = KvStore::open(temp_dir.path())?;
+    Command::cargo_bin("kvs-server")
+        .unwrap()
+        .args(["--addr", "127.0.0.1:0"])
+        .current_dir(&temp_dir)
+        .assert()
+        .code(KvError::Locked(String::new()).exit_code());
+    drop(store);
+    Ok(())
+}
@@ -0,0 +1,108 @@
+mod common;
+
+use common::log_files;
+use kv::{KvStore, Result};
+use std::thread;
+use tempfile::TempDir;
+
+#[test]
+fn snapshot_is_frozen() -> Result<()> {
+    let temp_dir = TempDir::new().expect("unable to create temporary working directory");
+    let store = KvStore::open(temp_dir.path())?;
+    for i in 0..5 {
+        store.set(format!("key{}", i), format!("value{}", i))?;
+    }
+
+    let snapshot = store.snapshot();
+    store.set("key1".to_owned(), "changed".to_owned())?;
+    store.remove("key2".to_owned())?;
+    store.set("key25".to_owned(), "new".to_owned())?;
+    store.set("key5".to_owned(), "new".to_owned())?;
+
+    assert_eq!(snapshot.get("key1".to_owned())?, Some("value1".to_owned()));
+    assert_eq!(snapshot.get("key2".to_owned())?, Some("value2".to_owned()));
+    assert_eq!(snapshot.get("key25".to_owned())?, None);
+    assert_eq!(store.get("key25".to_owned())?, Some("new".to_owned()));
+
+    let pairs = snapshot.scan(..).collect::<Result<Vec<_>>>()?;
+    let expected: Vec<_> = (0..5)
+        .map(|i| (format!("key{}", i), format!("value{}", i)))
+        .collect();
+    assert_eq!(pairs, expected);
+
+    let keys: Vec<String> = snapshot
+        .scan("key1".to_owned()..)
+        .rev()
+        .take(3)
+        .map(|pair| pair.map(|(key, _)| key))
+        .collect::<Result<_>>()?;
+    assert_eq!(keys, vec!["key4", "key3", "key2"]);
+    assert_eq!(snapshot.scan_prefix("key2").count(), 1);
+    assert_eq!(store.scan_prefix("key2").count(), 1);
+    Ok(())
+}
+
+// An export over a snapshot sees one state while another thread keeps writing.
+#[test]
+fn export_while_writing() -> Result<()> {
+    let temp_dir = TempDir::new().expect("unable to create temporary working directory");
+    let store = KvStore::open(temp_dir.path())?;
+    for i in 0..500 {
+        store.set(format!("key{:03}", i), "old".to_owned())?;
+    }
+
+    let snapshot = store.snapshot();
+    let writer = store.clone();
+    let handle = thread::spawn(move || -> Result<()> {
+        for i in 0..500 {
+            writer.set(format!("key{:03}", i), "new".to_owned())?;
+            if i % 2 == 0 {
+                writer.remove(format!("key{:03}", i))?;
+            }
+            writer.set(format!("extra{}", i), "new".to_owned())?;
+        }
+        writer.compaction()
+    });
+
+    let mut count = 0;
+    for pair in snapshot.scan(..) {
+        let (key, value) = pair?;
+        assert!(key.starts_with("key"));
+        assert_eq!(value, "old");
+        count += 1;
+    }
+    assert_eq!(count, 500);
+    handle.join().unwrap()?;
+
+    assert_eq!(snapshot.scan(..).count(), 500);
+    assert_eq!(store.scan_prefix("key").count(), 250);
+    Ok(())
+}
+
+// Compaction keeps the generations a live snapshot still reads from, and drops them after.
+#[test]
+fn snapshot_keeps_generations() -> Result<()> {
+    let temp_dir = TempDir::new().expect("unable to create temporary working directory");
+    let store = KvStore::open(temp_dir.path())?;
+    for i in 0..100 {
+        store.set(format!("key{}", i), format!("value{}", i))?;
+    }
+    let snapshot = store.snapshot();
+    for i in 0..100 {
+        store.remove(format!("key{}", i))?;
+    }
+    store.compaction()?;
+    assert_eq!(store.scan(..).count(), 0);
+    for i in 0..100 {
+        assert_eq!(
+            snapshot.get(format!("key{}", i))?,
+            Some(format!("value{}", i))
+        );
+    }
+    let kept = log_files(temp_dir.path()).len();
+    drop(snapshot);
+
+    store.compaction()?;
+    assert!(log_files(temp_dir.path()).len() < kept);
+    Ok(())
+}
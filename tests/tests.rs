@@ -1,4 +1,10 @@
+// the CLI tests pass their arguments as borrowed arrays
+#![allow(clippy::needless_borrows_for_generic_args)]
+
+mod common;
+
 use assert_cmd::prelude::*;
+use common::kv;
 use kv::{KvStore, Result};
 use predicates::ord::eq;
 use predicates::str::{contains, is_empty, PredicateStrExt};
@@ -83,7 +89,7 @@ fn cli_set() {
 fn cli_get_stored() -> Result<()> {
     let temp_dir = TempDir::new().expect("unable to create temporary working directory");
 
-    let mut store = KvStore::open(temp_dir.path())?;
+    let store = KvStore::open(temp_dir.path())?;
     store.set("key1".to_owned(), "value1".to_owned())?;
     store.set("key2".to_owned(), "value2".to_owned())?;
     drop(store);
@@ -112,7 +118,7 @@ fn cli_get_stored() -> Result<()> {
 fn cli_rm_stored() -> Result<()> {
     let temp_dir = TempDir::new().expect("unable to create temporary working directory");
 
-    let mut store = KvStore::open(temp_dir.path())?;
+    let store = KvStore::open(temp_dir.path())?;
     store.set("key1".to_owned(), "value1".to_owned())?;
     drop(store);
 
@@ -196,11 +202,179 @@ fn cli_invalid_rm() {
         .failure();
 }
 
+// `kvs scan` prints the pairs of a range in key order, `kvs keys` only their keys.
+#[test]
+fn cli_scan_and_keys() -> Result<()> {
+    let temp_dir = TempDir::new().expect("unable to create temporary working directory");
+
+    let store = KvStore::open(temp_dir.path())?;
+    for key in &["a1", "b1", "b2", "b3", "c1"] {
+        store.set(key.to_string(), format!("value_{}", key))?;
+    }
+    drop(store);
+
+    Command::cargo_bin("kv")
+        .unwrap()
+        .args(["scan", "--start", "b1", "--end", "c1"])
+        .current_dir(&temp_dir)
+        .assert()
+        .success()
+        .stdout(eq("b1\tvalue_b1\nb2\tvalue_b2\nb3\tvalue_b3\n"));
+
+    Command::cargo_bin("kv")
+        .unwrap()
+        .args(["keys", "--prefix", "b", "--reverse", "--limit", "2"])
+        .current_dir(&temp_dir)
+        .assert()
+        .success()
+        .stdout(eq("b3\nb2\n"));
+
+    Command::cargo_bin("kv")
+        .unwrap()
+        .args(["keys"])
+        .current_dir(&temp_dir)
+        .assert()
+        .success()
+        .stdout(eq("a1\nb1\nb2\nb3\nc1\n"));
+
+    Command::cargo_bin("kv")
+        .unwrap()
+        .args(["keys", "--prefix", "b", "--start", "a"])
+        .current_dir(&temp_dir)
+        .assert()
+        .failure();
+    Ok(())
+}
+
+// Conditional `kvs set` and `kvs rm` exit with 2 when their condition does not hold.
+#[test]
+fn cli_conditional_writes() -> Result<()> {
+    let temp_dir = TempDir::new().expect("unable to create temporary working directory");
+    kv(temp_dir.path())
+        .args(&["set", "key", "v1", "--if-absent"])
+        .assert()
+        .success()
+        .stdout(is_empty());
+    kv(temp_dir.path())
+        .args(&["set", "key", "v2", "--if-absent"])
+        .assert()
+        .code(2)
+        .stdout(eq("Condition not met").trim());
+    kv(temp_dir.path())
+        .args(&["set", "key", "v3", "--expect", "v2"])
+        .assert()
+        .code(2);
+    kv(temp_dir.path())
+        .args(&["set", "key", "v3", "--expect", "v1"])
+        .assert()
+        .success();
+    kv(temp_dir.path())
+        .args(&["rm", "key", "--expect", "v1"])
+        .assert()
+        .code(2);
+    kv(temp_dir.path())
+        .args(&["get", "key"])
+        .assert()
+        .success()
+        .stdout(eq("v3").trim());
+    kv(temp_dir.path())
+        .args(&["rm", "key", "--expect", "v3"])
+        .assert()
+        .success();
+    kv(temp_dir.path())
+        .args(&["rm", "key", "--expect", "v3"])
+        .assert()
+        .code(2);
+    kv(temp_dir.path())
+        .args(&["set", "key", "v", "--if-absent", "--expect", "v"])
+        .assert()
+        .failure();
+
+    let store = KvStore::open(temp_dir.path())?;
+    assert_eq!(store.get("key".to_owned())?, None);
+    Ok(())
+}
+
+// `kvs incr` and `kvs decr` print the new value of the counter.
+#[test]
+fn cli_incr() {
+    let temp_dir = TempDir::new().expect("unable to create temporary working directory");
+    kv(temp_dir.path())
+        .args(&["incr", "counter"])
+        .assert()
+        .success()
+        .stdout(eq("1\n"));
+    kv(temp_dir.path())
+        .args(&["incr", "counter", "10"])
+        .assert()
+        .success()
+        .stdout(eq("11\n"));
+    kv(temp_dir.path())
+        .args(&["decr", "counter", "20"])
+        .assert()
+        .success()
+        .stdout(eq("-9\n"));
+    kv(temp_dir.path())
+        .args(&["set", "name", "value"])
+        .assert()
+        .success();
+    kv(temp_dir.path())
+        .args(&["incr", "name"])
+        .assert()
+        .failure()
+        .stderr(contains("not an integer"));
+    kv(temp_dir.path())
+        .args(&["incr", "counter", "one"])
+        .assert()
+        .failure();
+}
+
+// Every kind of failure exits with its own code.
+#[test]
+fn cli_exit_codes() {
+    let temp_dir = TempDir::new().expect("unable to create temporary working directory");
+    kv(temp_dir.path())
+        .args(&["rm", "key"])
+        .assert()
+        .code(3)
+        .stdout(eq("Key not found").trim());
+    kv(temp_dir.path())
+        .args(&["set", "key", "value"])
+        .assert()
+        .success();
+    kv(temp_dir.path()).args(&["incr", "key"]).assert().code(15);
+    kv(temp_dir.path())
+        .args(&["set", "counter", "9223372036854775807"])
+        .assert()
+        .success();
+    kv(temp_dir.path())
+        .args(&["incr", "counter"])
+        .assert()
+        .code(16)
+        .stderr(contains("overflow"));
+    kv(temp_dir.path())
+        .args(&["get", "key", "--engine", "memory"])
+        .assert()
+        .code(12)
+        .stderr(contains("Wrong engine"));
+    kv(temp_dir.path())
+        .args(&["get", "key", "--durability", "sometimes"])
+        .assert()
+        .code(1);
+    kv(temp_dir.path())
+        .args(&["--help"])
+        .assert()
+        .success()
+        .stdout(contains("EXIT CODES:"))
+        .stdout(contains("15  value is not an integer"))
+        .stdout(contains("22  I/O error on a file of the store"));
+}
+
 // Should get previously stored value.
 #[test]
 fn get_stored_value() -> Result<()> {
     let temp_dir = TempDir::new().expect("unable to create temporary working directory");
-    let mut store = KvStore::open(temp_dir.path())?;
+    let store = KvStore::open(temp_dir.path())?;
 
     store.set("key1".to_owned(), "value1".to_owned())?;
     store.set("key2".to_owned(), "value2".to_owned())?;
@@ -210,7 +384,7 @@ fn get_stored_value() -> Result<()> {
 
     // Open from disk again and check persistent data.
     drop(store);
-    let mut store = KvStore::open(temp_dir.path())?;
+    let store = KvStore::open(temp_dir.path())?;
     assert_eq!(store.get("key1".to_owned())?, Some("value1".to_owned()));
     assert_eq!(store.get("key2".to_owned())?, Some("value2".to_owned()));
 
@@ -221,7 +395,7 @@ fn get_stored_value() -> Result<()> {
 #[test]
 fn overwrite_value() -> Result<()> {
     let temp_dir = TempDir::new().expect("unable to create temporary working directory");
-    let mut store = KvStore::open(temp_dir.path())?;
+    let store = KvStore::open(temp_dir.path())?;
 
     store.set("key1".to_owned(), "value1".to_owned())?;
     assert_eq!(store.get("key1".to_owned())?, Some("value1".to_owned()));
@@ -230,7 +404,7 @@ fn overwrite_value() -> Result<()> {
 
     // Open from disk again and check persistent data.
     drop(store);
-    let mut store = KvStore::open(temp_dir.path())?;
+    let store = KvStore::open(temp_dir.path())?;
     assert_eq!(store.get("key1".to_owned())?, Some("value2".to_owned()));
     store.set("key1".to_owned(), "value3".to_owned())?;
     assert_eq!(store.get("key1".to_owned())?, Some("value3".to_owned()));
@@ -242,14 +416,14 @@ fn overwrite_value() -> Result<()> {
 #[test]
 fn get_non_existent_value() -> Result<()> {
     let temp_dir = TempDir::new().expect("unable to create temporary working directory");
-    let mut store = KvStore::open(temp_dir.path())?;
+    let store = KvStore::open(temp_dir.path())?;
 
     store.set("key1".to_owned(), "value1".to_owned())?;
     assert_eq!(store.get("key2".to_owned())?, None);
 
     // Open from disk again and check persistent data.
     drop(store);
-    let mut store = KvStore::open(temp_dir.path())?;
+    let store = KvStore::open(temp_dir.path())?;
     assert_eq!(store.get("key2".to_owned())?, None);
 
     Ok(())
@@ -258,7 +432,7 @@ fn get_non_existent_value() -> Result<()> {
 #[test]
 fn remove_non_existent_key() -> Result<()> {
     let temp_dir = TempDir::new().expect("unable to create temporary working directory");
-    let mut store = KvStore::open(temp_dir.path())?;
+    let store = KvStore::open(temp_dir.path())?;
     assert!(store.remove("key1".to_owned()).is_err());
     Ok(())
 }
@@ -266,7 +440,7 @@ fn remove_non_existent_key() -> Result<()> {
 #[test]
 fn remove_key() -> Result<()> {
     let temp_dir = TempDir::new().expect("unable to create temporary working directory");
-    let mut store = KvStore::open(temp_dir.path())?;
+    let store = KvStore::open(temp_dir.path())?;
     store.set("key1".to_owned(), "value1".to_owned())?;
     assert!(store.remove("key1".to_owned()).is_ok());
     assert_eq!(store.get("key1".to_owned())?, None);
@@ -278,7 +452,7 @@ fn remove_key() -> Result<()> {
 #[test]
 fn compaction() -> Result<()> {
     let temp_dir = TempDir::new().expect("unable to create temporary working directory");
-    let mut store = KvStore::open(temp_dir.path())?;
+    let store = KvStore::open(temp_dir.path())?;
 
     let dir_size = || {
         let entries = WalkDir::new(temp_dir.path()).into_iter();
@@ -310,7 +484,7 @@ fn compaction() -> Result<()> {
 
         drop(store);
         // reopen and check content.
-        let mut store = KvStore::open(temp_dir.path())?;
+        let store = KvStore::open(temp_dir.path())?;
         for key_id in 0..1000 {
             let key = format!("key{}", key_id);
             assert_eq!(store.get(key)?, Some(format!("{}", iter)));
@@ -0,0 +1,133 @@
+mod common;
+
+use common::log_files;
+use kv::error::KvError;
+use kv::{KvStore, Result};
+use tempfile::TempDir;
+
+#[test]
+fn commit_applies_writes() -> Result<()> {
+    let temp_dir = TempDir::new().expect("unable to create temporary working directory");
+    let store = KvStore::open(temp_dir.path())?;
+    store.set("counter".to_owned(), "1".to_owned())?;
+    store.set("old".to_owned(), "value".to_owned())?;
+
+    let mut txn = store.begin();
+    let counter: u64 = txn.get("counter".to_owned())?.unwrap().parse().unwrap();
+    txn.set("counter".to_owned(), (counter + 1).to_string());
+    txn.remove("old".to_owned())?;
+    assert!(matches!(
+        txn.remove("missing".to_owned()),
+        Err(KvError::KeyNotFound(_))
+    ));
+    // the transaction sees its own writes, nobody else does before the commit
+    assert_eq!(txn.get("counter".to_owned())?, Some("2".to_owned()));
+    assert_eq!(txn.get("old".to_owned())?, None);
+    assert_eq!(store.get("counter".to_owned())?, Some("1".to_owned()));
+    txn.commit()?;
+
+    assert_eq!(store.get("counter".to_owned())?, Some("2".to_owned()));
+    assert_eq!(store.get("old".to_owned())?, None);
+    drop(store);
+
+    let store = KvStore::open(temp_dir.path())?;
+    assert_eq!(store.get("counter".to_owned())?, Some("2".to_owned()));
+    assert_eq!(store.get("old".to_owned())?, None);
+    Ok(())
+}
+
+#[test]
+fn rollback_on_drop() -> Result<()> {
+    let temp_dir = TempDir::new().expect("unable to create temporary working directory");
+    let store = KvStore::open(temp_dir.path())?;
+    let mut txn = store.begin();
+    txn.set("key1".to_owned(), "value1".to_owned());
+    drop(txn);
+    assert_eq!(store.get("key1".to_owned())?, None);
+    Ok(())
+}
+
+// Reads see the store as it was when the transaction began.
+#[test]
+fn snapshot_reads() -> Result<()> {
+    let temp_dir = TempDir::new().expect("unable to create temporary working directory");
+    let store = KvStore::open(temp_dir.path())?;
+    store.set("key1".to_owned(), "value1".to_owned())?;
+    store.set("key2".to_owned(), "value2".to_owned())?;
+
+    let mut txn = store.begin();
+    store.set("key1".to_owned(), "changed".to_owned())?;
+    store.remove("key2".to_owned())?;
+    store.set("key3".to_owned(), "value3".to_owned())?;
+
+    assert_eq!(txn.get("key1".to_owned())?, Some("value1".to_owned()));
+    assert_eq!(txn.get("key2".to_owned())?, Some("value2".to_owned()));
+    assert_eq!(txn.get("key3".to_owned())?, None);
+
+    // the keys it read changed meanwhile
+    txn.set("key4".to_owned(), "value4".to_owned());
+    assert!(matches!(txn.commit(), Err(KvError::Conflict(_))));
+    Ok(())
+}
+
+#[test]
+fn conflicting_commits() -> Result<()> {
+    let temp_dir = TempDir::new().expect("unable to create temporary working directory");
+    let store = KvStore::open(temp_dir.path())?;
+    store.set("key1".to_owned(), "value1".to_owned())?;
+
+    // a key written by both
+    let mut first = store.begin();
+    let mut second = store.begin();
+    first.set("key1".to_owned(), "first".to_owned());
+    second.set("key1".to_owned(), "second".to_owned());
+    first.commit()?;
+    assert!(matches!(second.commit(), Err(KvError::Conflict(ref key)) if key == "key1"));
+    assert_eq!(store.get("key1".to_owned())?, Some("first".to_owned()));
+
+    // a key read by one and written by the other
+    let mut txn = store.begin();
+    txn.get("key1".to_owned())?;
+    txn.set("key2".to_owned(), "value2".to_owned());
+    store.set("key1".to_owned(), "other".to_owned())?;
+    assert!(matches!(txn.commit(), Err(KvError::Conflict(_))));
+    assert_eq!(store.get("key2".to_owned())?, None);
+
+    // keys nobody else touched
+    let mut txn = store.begin();
+    txn.get("key1".to_owned())?;
+    txn.set("key2".to_owned(), "value2".to_owned());
+    store.set("key3".to_owned(), "value3".to_owned())?;
+    txn.commit()?;
+    assert_eq!(store.get("key2".to_owned())?, Some("value2".to_owned()));
+    Ok(())
+}
+
+// Compaction keeps the generations a live transaction still reads from.
+#[test]
+fn snapshot_survives_compaction() -> Result<()> {
+    let temp_dir = TempDir::new().expect("unable to create temporary working directory");
+    let store = KvStore::open(temp_dir.path())?;
+    for i in 0..100 {
+        store.set(format!("key{}", i), format!("value{}", i))?;
+    }
+
+    let mut txn = store.begin();
+    for i in 0..100 {
+        store.set(format!("key{}", i), format!("new{}", i))?;
+    }
+    store.compaction()?;
+    store.compaction()?;
+    for i in 0..100 {
+        assert_eq!(txn.get(format!("key{}", i))?, Some(format!("value{}", i)));
+    }
+    let kept = log_files(temp_dir.path()).len();
+    drop(txn);
+
+    store.compaction()?;
+    assert!(log_files(temp_dir.path()).len() < kept);
+    for i in 0..100 {
+        assert_eq!(store.get(format!("key{}", i))?, Some(format!("new{}", i)));
+    }
+    Ok(())
+}
@@ -0,0 +1,134 @@
+mod common;
+
+use assert_cmd::prelude::*;
+use common::log_bytes;
+use kv::error::KvError;
+use kv::{KvStore, KvsEngine, MemStore, Result};
+use predicates::str::{contains, PredicateStrExt};
+use std::process::Command;
+use std::thread;
+use std::time::Duration;
+use tempfile::TempDir;
+
+const TTL: Duration = Duration::from_millis(200);
+
+fn check_ttl<E: KvsEngine>(engine: E) -> Result<()> {
+    engine.set("a".to_owned(), "kept".to_owned())?;
+    engine.set_with_ttl("b".to_owned(), "expiring".to_owned(), TTL)?;
+    engine.set_with_ttl("c".to_owned(), "expiring".to_owned(), TTL)?;
+    engine.set_with_ttl("d".to_owned(), "renewed".to_owned(), TTL)?;
+    engine.set("d".to_owned(), "renewed".to_owned())?;
+    assert_eq!(engine.get("b".to_owned())?, Some("expiring".to_owned()));
+    assert_eq!(engine.scan(..).count(), 4);
+
+    thread::sleep(TTL);
+    assert_eq!(engine.get("b".to_owned())?, None);
+    let keys: Vec<String> = engine
+        .scan(..)
+        .map(|pair| pair.map(|(key, _)| key))
+        .collect::<Result<_>>()?;
+    assert_eq!(keys, vec!["a", "d"]);
+    assert_eq!(engine.scan(..).rev().count(), 2);
+    assert!(matches!(
+        engine.remove("b".to_owned()),
+        Err(KvError::KeyNotFound(_))
+    ));
+    assert!(engine.set_if_absent("c".to_owned(), "again".to_owned())?);
+    assert_eq!(engine.get("c".to_owned())?, Some("again".to_owned()));
+    Ok(())
+}
+
+#[test]
+fn kvs_engine_ttl() -> Result<()> {
+    let temp_dir = TempDir::new().expect("unable to create temporary working directory");
+    check_ttl(KvStore::open(temp_dir.path())?)
+}
+
+#[test]
+fn memory_engine_ttl() -> Result<()> {
+    check_ttl(MemStore::new())
+}
+
+// The expiry is part of the record, so it holds after replaying the log
+// and after reading the positions from a hint file.
+#[test]
+fn ttl_survives_open() -> Result<()> {
+    let temp_dir = TempDir::new().expect("unable to create temporary working directory");
+    let store = KvStore::open(temp_dir.path())?;
+    store.set_with_ttl("short".to_owned(), "value".to_owned(), TTL)?;
+    store.set_with_ttl(
+        "long".to_owned(),
+        "value".to_owned(),
+        Duration::from_secs(3600),
+    )?;
+    drop(store);
+
+    let store = KvStore::open(temp_dir.path())?;
+    assert_eq!(store.get("short".to_owned())?, Some("value".to_owned()));
+    store.compaction()?;
+    drop(store);
+
+    let store = KvStore::open(temp_dir.path())?;
+    thread::sleep(TTL);
+    assert_eq!(store.get("short".to_owned())?, None);
+    assert_eq!(store.get("long".to_owned())?, Some("value".to_owned()));
+    Ok(())
+}
+
+// Compaction does not copy expired records.
+#[test]
+fn expired_keys_dropped_by_compaction() -> Result<()> {
+    let temp_dir = TempDir::new().expect("unable to create temporary working directory");
+    let store = KvStore::open(temp_dir.path())?;
+    store.set("kept".to_owned(), "value".to_owned())?;
+    store.compaction()?;
+    let live = log_bytes(temp_dir.path());
+
+    for i in 0..100 {
+        store.set_with_ttl(format!("key{}", i), "value".repeat(10), TTL)?;
+    }
+    thread::sleep(TTL);
+    store.compaction()?;
+    assert_eq!(log_bytes(temp_dir.path()), live);
+    drop(store);
+
+    let store = KvStore::open(temp_dir.path())?;
+    assert_eq!(store.scan(..).count(), 1);
+    assert_eq!(store.get("key0".to_owned())?, None);
+    Ok(())
+}
+
+// `kvs set <KEY> <VALUE> --ttl <SECONDS>` sets a key that expires.
+#[test]
+fn cli_set_ttl() {
+    let temp_dir = TempDir::new().expect("unable to create temporary working directory");
+    Command::cargo_bin("kv")
+        .unwrap()
+        .args(["set", "key", "value", "--ttl", "1"])
+        .current_dir(&temp_dir)
+        .assert()
+        .success();
+    Command::cargo_bin("kv")
+        .unwrap()
+        .args(["get", "key"])
+        .current_dir(&temp_dir)
+        .assert()
+        .success()
+        .stdout(contains("value"));
+
+    thread::sleep(Duration::from_secs(1));
+    Command::cargo_bin("kv")
+        .unwrap()
+        .args(["get", "key"])
+        .current_dir(&temp_dir)
+        .assert()
+        .success()
+        .stdout(contains("Key not found").trim());
+
+    Command::cargo_bin("kv")
+        .unwrap()
+        .args(["set", "key", "value", "--ttl", "soon"])
+        .current_dir(&temp_dir)
+        .assert()
+        .failure();
+}